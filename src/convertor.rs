@@ -1,13 +1,17 @@
-//! Convert the  AST into an HTML string
+//! Convert the AST into an HTML string or plain text, or back into markdown
 
+use std::collections::HashMap;
+#[cfg(feature = "async")]
+use std::future::Future;
+use std::time::{SystemTime, UNIX_EPOCH};
 use html_escape;
-use crate::parser::Expression;
+use crate::parser::{is_language_tag, references, split_emoji_id, Expression, GuildNavigationKind, TimestampStyle};
 
 trait Callback: Fn(&str) -> (String, Option<String>) {}
 
 impl<T: Fn(&str) -> (String, Option<String>)> Callback for T {}
 
-// Store all the callbacks in a struct so we can pass it around easily during recursion
+// Store all the callbacks in a struct so we can use them as a `MentionResolver`
 struct Callbacks<A, B, C, D> {
     emoji: A,
     user: B,
@@ -15,64 +19,754 @@ struct Callbacks<A, B, C, D> {
     channel: D,
 }
 
-// Generates HTML from the AST
-fn traverse(ast: Vec<Expression>, callbacks: &Callbacks<impl Callback, impl Callback, impl Callback, impl Callback>, first: bool) -> String {
-    // String to store the final HTML
-    let mut final_html = String::new();
+/// What a user mention resolves to
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct UserInfo {
+    /// The name shown after the `@`
+    pub name: String,
+    /// The URL of the user's avatar, shown before the name
+    pub avatar: Option<String>,
+    /// The color of the name, like the color of the user's highest role
+    pub color: Option<String>,
+}
+
+/// What a role mention resolves to
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct RoleInfo {
+    /// The name shown after the `@`
+    pub name: String,
+    /// The color of the role. `None` uses the default color of `#afafaf`.
+    pub color: Option<String>,
+}
+
+/// What a channel mention resolves to
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ChannelInfo {
+    /// The name shown after the `#`
+    pub name: String,
+    /// The kind of channel
+    pub kind: ChannelKind,
+}
+
+/// The kinds of channels that can be mentioned
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChannelKind {
+    #[default]
+    Text,
+    Voice,
+    Announcement,
+    Stage,
+    Forum,
+    Thread,
+}
+
+impl ChannelKind {
+    /// Gets the name of the channel kind, used for the `data-kind` attribute in the generated HTML
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChannelKind::Text => "text",
+            ChannelKind::Voice => "voice",
+            ChannelKind::Announcement => "announcement",
+            ChannelKind::Stage => "stage",
+            ChannelKind::Forum => "forum",
+            ChannelKind::Thread => "thread",
+        }
+    }
+}
+
+/// Resolves custom emoji, mentions and links to messages while converting the AST
+///
+/// Every method has a default implementation that uses the ID as the name, like `to_html` does,
+/// so a resolver only has to implement the methods for what it can look up.
+///
+/// Users, roles and channels that can't be found can be resolved to `None`. They're shown like
+/// discord shows them, as `@unknown-user`, `@deleted-role` and `#deleted-channel`, and get the
+/// `unresolved` class in the generated HTML.
+///
+/// ```
+/// use discord_markdown::{parser::parse, convertor::*};
+///
+/// struct Guild;
+///
+/// impl MentionResolver for Guild {
+///     fn user(&self, _: &str) -> Option<UserInfo> {
+///         Some(UserInfo { name: "Jane Doe".to_owned(), color: Some("#00ff00".to_owned()), avatar: None })
+///     }
+///
+///     fn channel(&self, id: &str) -> Option<ChannelInfo> {
+///         if id == "333666999333666999" {
+///             Some(ChannelInfo { name: "General".to_owned(), kind: ChannelKind::Voice })
+///         } else {
+///             None
+///         }
+///     }
+/// }
+///
+/// assert_eq!(
+///     to_html_with_resolver(parse("<@111222333111222333> <#333666999333666999> <#123>"), &Guild),
+///     "<span class=\"user\" style=\"color: #00ff00\">@Jane Doe</span> \
+///     <span class=\"channel\" data-id=\"333666999333666999\" data-kind=\"voice\">#General</span> \
+///     <span class=\"channel unresolved\" data-id=\"123\">#deleted-channel</span>",
+/// );
+/// ```
+pub trait MentionResolver {
+    /// Resolves the user with the given ID, or `None` if there's no such user
+    fn user(&self, id: &str) -> Option<UserInfo> {
+        Some(UserInfo { name: id.to_owned(), ..UserInfo::default() })
+    }
+
+    /// Resolves the role with the given ID, or `None` if there's no such role
+    fn role(&self, id: &str) -> Option<RoleInfo> {
+        Some(RoleInfo { name: id.to_owned(), ..RoleInfo::default() })
+    }
+
+    /// Resolves the channel with the given ID, or `None` if there's no such channel
+    fn channel(&self, id: &str) -> Option<ChannelInfo> {
+        Some(ChannelInfo { name: id.to_owned(), ..ChannelInfo::default() })
+    }
+
+    /// Gets the URL of the custom emoji with the given ID, used as the `src` attribute of the
+    /// `<img>` tag
+    fn emoji(&self, id: &str, animated: bool) -> String {
+        format!("{}.{}", id, if animated { "gif" } else { "png" })
+    }
+
+    /// Gets the name to show after the `/` for a slash command mention, given the command with its
+    /// subcommand group and subcommand separated by spaces (like `settings user color`)
+    fn slash_command(&self, command: &str) -> String {
+        command.to_owned()
+    }
+
+    /// Gets a preview of the message with the given ID, shown after the name of the channel in
+    /// links to messages
+    fn message(&self, _id: &str) -> Option<String> {
+        None
+    }
+
+    /// Gets the current time as a Unix timestamp, which relative timestamps are shown relative to
+    fn now(&self) -> i64 {
+        unix_now()
+    }
+}
+
+impl<A: Callback, B: Callback, C: Callback, D: Callback> MentionResolver for Callbacks<A, B, C, D> {
+    fn user(&self, id: &str) -> Option<UserInfo> {
+        Some(UserInfo { name: (self.user)(id).0, ..UserInfo::default() })
+    }
+
+    fn role(&self, id: &str) -> Option<RoleInfo> {
+        let (name, color) = (self.role)(id);
+        Some(RoleInfo { name, color })
+    }
+
+    fn channel(&self, id: &str) -> Option<ChannelInfo> {
+        Some(ChannelInfo { name: (self.channel)(id).0, ..ChannelInfo::default() })
+    }
+
+    fn emoji(&self, id: &str, animated: bool) -> String {
+        // The emoji callback is given the file name
+        (self.emoji)(&format!("{}.{}", id, if animated { "gif" } else { "png" })).0
+    }
+}
+
+// The `MentionResolver` that uses the IDs as the names
+struct Ids;
+
+impl MentionResolver for Ids {}
+
+/// The IDs of the users, roles, channels and custom emoji in an AST, without duplicates
+///
+/// Channels include the channels of links to messages, and custom emoji are stored as their ID
+/// and whether they're animated. Emoji are looked up by ID, so an emoji used both as animated and
+/// not animated is only in here once, as it was used first.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct MentionIds {
+    pub users: Vec<String>,
+    pub roles: Vec<String>,
+    pub channels: Vec<String>,
+    pub emoji: Vec<(String, bool)>,
+}
+
+impl MentionIds {
+    /// Collects the IDs of everything in the AST that a `MentionResolver` is used for
+    pub fn collect(ast: &[Expression]) -> Self {
+        let references = references(ast);
+        let mut emoji = Vec::new();
+        // The same emoji can be used with different names, and with or without `a:`
+        for reference in references.emoji {
+            if !emoji.iter().any(|(id, _)| id == reference.id) {
+                emoji.push((reference.id.to_string(), reference.animated));
+            }
+        }
+        MentionIds {
+            users: references.users.into_iter().map(String::from).collect(),
+            roles: references.roles.into_iter().map(String::from).collect(),
+            channels: references.channels.into_iter().map(String::from).collect(),
+            emoji,
+        }
+    }
+}
+
+/// A `MentionResolver` for mentions that were looked up in advance, like all the `MentionIds` of
+/// a message at once
+///
+/// Users, roles and channels that are missing are unresolved, and custom emoji that are missing
+/// use their file name as the URL.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ResolvedMentions {
+    pub users: HashMap<String, UserInfo>,
+    pub roles: HashMap<String, RoleInfo>,
+    pub channels: HashMap<String, ChannelInfo>,
+    /// The URLs of custom emoji
+    pub emoji: HashMap<String, String>,
+}
+
+impl MentionResolver for ResolvedMentions {
+    fn user(&self, id: &str) -> Option<UserInfo> {
+        self.users.get(id).cloned()
+    }
+
+    fn role(&self, id: &str) -> Option<RoleInfo> {
+        self.roles.get(id).cloned()
+    }
+
+    fn channel(&self, id: &str) -> Option<ChannelInfo> {
+        self.channels.get(id).cloned()
+    }
+
+    fn emoji(&self, id: &str, animated: bool) -> String {
+        self.emoji.get(id).cloned().unwrap_or_else(|| Ids.emoji(id, animated))
+    }
+}
+
+// The names discord shows for mentions that couldn't be resolved
+const UNKNOWN_USER: &str = "unknown-user";
+const DELETED_ROLE: &str = "deleted-role";
+const DELETED_CHANNEL: &str = "deleted-channel";
+
+const MONTHS: [&str; 12] = [
+    "January", "February", "March", "April", "May", "June",
+    "July", "August", "September", "October", "November", "December",
+];
+
+const WEEKDAYS: [&str; 7] = [
+    "Sunday", "Monday", "Tuesday", "Wednesday", "Thursday", "Friday", "Saturday",
+];
+
+// Converts a Unix timestamp into a UTC (year, month, day, weekday, hour, minute, second) tuple,
+// using Howard Hinnant's `civil_from_days` algorithm for the date
+fn utc_date_time(unix: i64) -> (i64, usize, i64, usize, i64, i64, i64) {
+    let days = unix.div_euclid(86400);
+    let seconds = unix.rem_euclid(86400);
+    // 1970-01-01 was a Thursday
+    let weekday = (days + 4).rem_euclid(7) as usize;
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    // Months are counted from March in this algorithm
+    let month = if mp < 10 { mp + 2 } else { mp - 10 } as usize;
+    let year = year_of_era + era * 400 + if month < 2 { 1 } else { 0 };
+    (year, month, day, weekday, seconds / 3600, seconds % 3600 / 60, seconds % 60)
+}
+
+// Formats the time between `unix` and `now` the way Discord does for relative timestamps
+fn format_relative(unix: i64, now: i64) -> String {
+    let seconds = (unix - now).abs();
+    let (minutes, hours, days) = ((seconds + 30) / 60, (seconds + 1800) / 3600, (seconds + 43200) / 86400);
+    let duration = match seconds {
+        s if s < 45 => String::from("a few seconds"),
+        s if s < 90 => String::from("a minute"),
+        s if s < 45 * 60 => format!("{} minutes", minutes),
+        s if s < 90 * 60 => String::from("an hour"),
+        s if s < 22 * 3600 => format!("{} hours", hours),
+        s if s < 36 * 3600 => String::from("a day"),
+        s if s < 26 * 86400 => format!("{} days", days),
+        s if s < 45 * 86400 => String::from("a month"),
+        s if s < 320 * 86400 => format!("{} months", (days * 10 + 152) / 304),
+        s if s < 548 * 86400 => String::from("a year"),
+        _ => format!("{} years", (days * 10 + 1826) / 3652),
+    };
+    if unix > now {
+        format!("in {}", duration)
+    } else {
+        format!("{} ago", duration)
+    }
+}
+
+// The current time as a Unix timestamp
+fn unix_now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs() as i64)
+}
+
+// Renders a timestamp in UTC with the given style, where `now` is the time relative timestamps are
+// relative to
+fn format_timestamp(unix: i64, style: TimestampStyle, now: i64) -> String {
+    let (year, month, day, weekday, hour, minute, second) = utc_date_time(unix);
+    match style {
+        TimestampStyle::ShortTime => format!("{:02}:{:02}", hour, minute),
+        TimestampStyle::LongTime => format!("{:02}:{:02}:{:02}", hour, minute, second),
+        TimestampStyle::ShortDate => format!("{:02}/{:02}/{:04}", day, month + 1, year),
+        TimestampStyle::LongDate => format!("{} {} {}", day, MONTHS[month], year),
+        TimestampStyle::ShortDateTime => {
+            format!("{} {} {} {:02}:{:02}", day, MONTHS[month], year, hour, minute)
+        }
+        TimestampStyle::LongDateTime => format!(
+            "{}, {} {} {} {:02}:{:02}",
+            WEEKDAYS[weekday], day, MONTHS[month], year, hour, minute,
+        ),
+        TimestampStyle::Relative => format_relative(unix, now),
+    }
+}
+
+// The name shown for links to the special pages of a server
+fn guild_navigation_name(kind: GuildNavigationKind) -> &'static str {
+    match kind {
+        GuildNavigationKind::Customize => "Channels & Roles",
+        GuildNavigationKind::Browse => "Browse Channels",
+        GuildNavigationKind::Guide | GuildNavigationKind::Home => "Server Guide",
+        GuildNavigationKind::LinkedRoles(_) => "Linked Roles",
+    }
+}
+
+// The HTML for the parts of the output that depend on the resolver, shared by the default
+// `Renderer` implementation and `ResolvedRenderer`. Names, colors and paths from the resolver could come
+// from users, so they're escaped.
+
+fn custom_emoji_html(name: &str, path: &str, wumboji: bool) -> String {
+    format!(
+        "<img src=\"{0}\" alt=\"{1}\" class=\"emoji{2}\" title=\"{1}\"></img>",
+        html_escape::encode_double_quoted_attribute(path),
+        name,
+        if wumboji { " wumboji" } else { "" },
+    )
+}
+
+fn user_html(user: Option<UserInfo>) -> String {
+    let user = match user {
+        Some(user) => user,
+        None => return format!("<span class=\"user unresolved\">@{}</span>", UNKNOWN_USER),
+    };
+    format!(
+        "<span class=\"user\"{}>{}@{}</span>",
+        user.color
+            .as_ref()
+            .map(|color| format!(" style=\"color: {}\"", html_escape::encode_double_quoted_attribute(color)))
+            .unwrap_or_default(),
+        user.avatar
+            .as_ref()
+            .map(|avatar| format!("<img src=\"{}\" class=\"avatar\"></img>", html_escape::encode_double_quoted_attribute(avatar)))
+            .unwrap_or_default(),
+        html_escape::encode_text(&user.name),
+    )
+}
+
+fn role_html(role: Option<RoleInfo>) -> String {
+    let role = match role {
+        Some(role) => role,
+        None => return format!("<span class=\"role unresolved\">@{}</span>", DELETED_ROLE),
+    };
+    format!(
+        "<div class=\"role\" style=\"color: {0}\">@{1}<span style=\"background-color: {0}\"></span></div>",
+        html_escape::encode_double_quoted_attribute(role.color.as_deref().unwrap_or("#afafaf")),
+        html_escape::encode_text(&role.name),
+    )
+}
+
+fn channel_html(id: &str, channel: Option<ChannelInfo>) -> String {
+    let channel = match channel {
+        Some(channel) => channel,
+        None => return format!("<span class=\"channel unresolved\" data-id=\"{}\">#{}</span>", id, DELETED_CHANNEL),
+    };
+    // Text channels are the default, so they don't get a `data-kind`
+    let kind = match channel.kind {
+        ChannelKind::Text => String::new(),
+        kind => format!(" data-kind=\"{}\"", kind.as_str()),
+    };
+    format!("<span class=\"channel\" data-id=\"{}\"{}>#{}</span>", id, kind, html_escape::encode_text(&channel.name))
+}
+
+fn slash_command_html(id: &str, command: &str) -> String {
+    format!("<span class=\"slash_command\" data-id=\"{}\">/{}</span>", id, html_escape::encode_text(command))
+}
+
+fn message_link_html(
+    guild: &str,
+    channel: &str,
+    message: Option<&str>,
+    suppress_embed: bool,
+    channel_info: Option<ChannelInfo>,
+    preview: Option<String>,
+) -> String {
+    let preview = preview
+        .map(|preview| format!(" › <span class=\"message_preview\">{}</span>", html_escape::encode_text(&preview)))
+        .unwrap_or_default();
+    format!(
+        "<a class=\"message_link\" href=\"https://discord.com/channels/{}/{}{}\" target=\"_blank\"{}>{}{}</a>",
+        guild,
+        channel,
+        message.map(|id| format!("/{}", id)).unwrap_or_default(),
+        if suppress_embed { " data-suppress-embed=\"true\"" } else { "" },
+        channel_html(channel, channel_info),
+        preview,
+    )
+}
+
+fn link_html(content: &str, href: &str, suppress_embed: bool) -> String {
+    format!(
+        "<a href=\"{}\" target=\"_blank\"{}>{}</a>",
+        html_escape::encode_double_quoted_attribute(href),
+        if suppress_embed { " data-suppress-embed=\"true\"" } else { "" },
+        content,
+    )
+}
+
+// Joins the words of a slash command mention with spaces, like `settings user color`
+fn slash_command_words(name: &str, subcommand_group: Option<&str>, subcommand: Option<&str>) -> String {
+    let command: Vec<_> = [Some(name), subcommand_group, subcommand].iter().flatten().copied().collect();
+    command.join(" ")
+}
+
+/// Renders each kind of `Expression` into a string
+///
+/// Every method has a default implementation that generates the same HTML as `to_html`, so a
+/// renderer only has to implement the methods for the expressions it wants to render differently.
+/// The methods for expressions with nested expressions are given the already rendered content.
+///
+/// ```
+/// use discord_markdown::{parser::parse, convertor::Renderer};
+///
+/// struct BlurredSpoilers;
+///
+/// impl Renderer for BlurredSpoilers {
+///     fn spoiler(&self, content: String) -> String {
+///         format!("<span class=\"blur\">{}</span>", content)
+///     }
+/// }
+///
+/// assert_eq!(
+///     BlurredSpoilers.render(parse("||**foo**||")),
+///     "<span class=\"blur\"><strong>foo</strong></span>",
+/// );
+/// ```
+pub trait Renderer {
+    /// Renders a vector of `Expression`s by calling the method for each expression
+    fn render(&self, ast: Vec<Expression>) -> String {
+        traverse(ast, self, true)
+    }
+
+    /// Renders `Expression::Text`
+    fn text(&self, text: &str) -> String {
+        // Escape HTML
+        html_escape::encode_text(text).to_string()
+    }
+
+    /// Renders `Expression::CustomEmoji`, where `id` is the ID of the emoji followed by either
+    /// `.png` or `.gif`. `wumboji` is true if the message only has emoji, so they should be shown
+    /// bigger.
+    fn custom_emoji(&self, name: &str, id: &str, wumboji: bool) -> String {
+        custom_emoji_html(name, id, wumboji)
+    }
+
+    /// Renders `Expression::Emoji`. `wumboji` is true if the message only has emoji, so they should
+    /// be shown bigger.
+    fn emoji(&self, emoji: &str, wumboji: bool) -> String {
+        format!("<span class=\"emoji{}\">{}</span>", if wumboji { " wumboji" } else { "" }, emoji)
+    }
+
+    /// Renders `Expression::User`
+    fn user(&self, id: &str) -> String {
+        user_html(Ids.user(id))
+    }
+
+    /// Renders `Expression::Role`
+    fn role(&self, id: &str) -> String {
+        role_html(Ids.role(id))
+    }
+
+    /// Renders `Expression::Everyone`
+    fn everyone(&self) -> String {
+        String::from("<span class=\"user everyone\">@everyone</span>")
+    }
+
+    /// Renders `Expression::Here`
+    fn here(&self) -> String {
+        String::from("<span class=\"user here\">@here</span>")
+    }
+
+    /// Renders `Expression::Channel`
+    fn channel(&self, id: &str) -> String {
+        channel_html(id, Ids.channel(id))
+    }
+
+    /// Renders `Expression::GuildNavigation`
+    fn guild_navigation(&self, kind: GuildNavigationKind) -> String {
+        let id = match kind {
+            GuildNavigationKind::LinkedRoles(id) => id,
+            _ => None,
+        };
+        format!(
+            "<span class=\"channel guild_navigation\" data-kind=\"{}\"{}>{}</span>",
+            kind.as_str(),
+            id.map(|id| format!(" data-id=\"{}\"", id)).unwrap_or_default(),
+            html_escape::encode_text(guild_navigation_name(kind)),
+        )
+    }
+
+    /// Renders `Expression::SlashCommand`
+    fn slash_command(&self, name: &str, subcommand_group: Option<&str>, subcommand: Option<&str>, id: &str) -> String {
+        slash_command_html(id, &slash_command_words(name, subcommand_group, subcommand))
+    }
+
+    /// Gets the current time as a Unix timestamp, which relative timestamps are shown relative to
+    fn now(&self) -> i64 {
+        unix_now()
+    }
+
+    /// Renders `Expression::Timestamp`
+    fn timestamp(&self, unix: i64, style: TimestampStyle) -> String {
+        let (year, month, day, _, hour, minute, second) = utc_date_time(unix);
+        format!(
+            "<time class=\"timestamp\" datetime=\"{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z\" data-style=\"{}\">{}</time>",
+            year, month + 1, day, hour, minute, second,
+            style.as_char(),
+            format_timestamp(unix, style, self.now()),
+        )
+    }
+
+    /// Renders `Expression::MessageLink`
+    fn message_link(&self, guild: &str, channel: &str, message: Option<&str>, suppress_embed: bool) -> String {
+        message_link_html(guild, channel, message, suppress_embed, Ids.channel(channel), None)
+    }
+
+    /// Renders `Expression::Hyperlink`
+    fn hyperlink(&self, text: &str, href: &str, suppress_embed: bool) -> String {
+        link_html(&html_escape::encode_text(text), href, suppress_embed)
+    }
+
+    /// Renders `Expression::MaskedLink`
+    fn masked_link(&self, content: String, href: &str, suppress_embed: bool) -> String {
+        link_html(&content, href, suppress_embed)
+    }
+
+    /// Renders `Expression::MultilineCode`
+    fn multiline_code(&self, language: Option<&str>, code: &str) -> String {
+        format!(
+            "<pre class=\"multiline_code{}\">{}</pre>",
+            language.map(|language| format!(" language-{}", language)).unwrap_or_default(),
+            html_escape::encode_text(code).replace("\n", "<br>"),
+        )
+    }
+
+    /// Renders `Expression::InlineCode`
+    fn inline_code(&self, code: &str) -> String {
+        format!("<span class=\"inline_code\">{}</span>", html_escape::encode_text(code).replace("\n", "<br>"))
+    }
+
+    /// Renders `Expression::Blockquote`
+    fn blockquote(&self, content: String) -> String {
+        format!("<blockquote>{}</blockquote>", content)
+    }
+
+    /// Renders `Expression::Heading`
+    fn heading(&self, level: u8, content: String) -> String {
+        format!("<h{0}>{1}</h{0}>", level, content)
+    }
+
+    /// Renders `Expression::Subtext`
+    fn subtext(&self, content: String) -> String {
+        format!("<div class=\"subtext\">{}</div>", content)
+    }
+
+    /// Renders `Expression::List`, given the rendered content of each item
+    fn list(&self, ordered: bool, start: u64, items: Vec<String>) -> String {
+        let items: String = items.iter().map(|item| format!("<li>{}</li>", item)).collect();
+        if !ordered {
+            format!("<ul>{}</ul>", items)
+        } else if start == 1 {
+            format!("<ol>{}</ol>", items)
+        } else {
+            format!("<ol start=\"{}\">{}</ol>", start, items)
+        }
+    }
+
+    /// Renders `Expression::Spoiler`
+    fn spoiler(&self, content: String) -> String {
+        format!("<span class=\"spoiler\">{}</span>", content)
+    }
+
+    /// Renders `Expression::Underline`
+    fn underline(&self, content: String) -> String {
+        format!("<u>{}</u>", content)
+    }
+
+    /// Renders `Expression::Strikethrough`
+    fn strikethrough(&self, content: String) -> String {
+        format!("<span class=\"strikethrough\">{}</span>", content)
+    }
+
+    /// Renders `Expression::Bold`
+    fn bold(&self, content: String) -> String {
+        format!("<strong>{}</strong>", content)
+    }
+
+    /// Renders `Expression::Italics`
+    fn italics(&self, content: String) -> String {
+        format!("<em>{}</em>", content)
+    }
+
+    /// Renders `Expression::Newline`
+    fn newline(&self) -> String {
+        String::from("<br>")
+    }
+}
+
+/// The `Renderer` that generates HTML with the default implementations, used by `to_html`
+pub struct HtmlRenderer;
+
+impl Renderer for HtmlRenderer {}
+
+/// The `Renderer` that generates HTML with the default implementations, except that it uses a
+/// `MentionResolver` for custom emoji, mentions and links to messages, used by
+/// `to_html_with_resolver`
+///
+/// Custom renderers can call its methods to render mentions the same way while rendering other
+/// expressions differently.
+///
+/// ```
+/// use discord_markdown::{parser::parse, convertor::*};
+///
+/// struct Users;
+///
+/// impl MentionResolver for Users {
+///     fn user(&self, _: &str) -> Option<UserInfo> {
+///         Some(UserInfo { name: "Jane Doe".to_owned(), ..UserInfo::default() })
+///     }
+/// }
+///
+/// struct BlurredSpoilers<'a>(ResolvedRenderer<'a, Users>);
+///
+/// impl Renderer for BlurredSpoilers<'_> {
+///     fn spoiler(&self, content: String) -> String {
+///         format!("<span class=\"blur\">{}</span>", content)
+///     }
+///
+///     fn user(&self, id: &str) -> String {
+///         self.0.user(id)
+///     }
+/// }
+///
+/// assert_eq!(
+///     BlurredSpoilers(ResolvedRenderer(&Users)).render(parse("||<@111222333111222333>||")),
+///     "<span class=\"blur\"><span class=\"user\">@Jane Doe</span></span>",
+/// );
+/// ```
+pub struct ResolvedRenderer<'a, R: ?Sized>(pub &'a R);
+
+impl<R: MentionResolver + ?Sized> Renderer for ResolvedRenderer<'_, R> {
+    fn custom_emoji(&self, name: &str, id: &str, wumboji: bool) -> String {
+        let (id, animated) = split_emoji_id(id);
+        custom_emoji_html(name, &self.0.emoji(id, animated), wumboji)
+    }
+
+    fn user(&self, id: &str) -> String {
+        user_html(self.0.user(id))
+    }
+
+    fn role(&self, id: &str) -> String {
+        role_html(self.0.role(id))
+    }
+
+    fn channel(&self, id: &str) -> String {
+        channel_html(id, self.0.channel(id))
+    }
+
+    fn slash_command(&self, name: &str, subcommand_group: Option<&str>, subcommand: Option<&str>, id: &str) -> String {
+        slash_command_html(id, &self.0.slash_command(&slash_command_words(name, subcommand_group, subcommand)))
+    }
+
+    fn message_link(&self, guild: &str, channel: &str, message: Option<&str>, suppress_embed: bool) -> String {
+        let preview = message.and_then(|id| self.0.message(id));
+        message_link_html(guild, channel, message, suppress_embed, self.0.channel(channel), preview)
+    }
+
+    fn now(&self) -> i64 {
+        self.0.now()
+    }
+}
+
+// Renders the AST with the given renderer
+fn traverse<R: Renderer + ?Sized>(ast: Vec<Expression>, renderer: &R, first: bool) -> String {
+    // String to store the final output
+    let mut final_output = String::new();
     // Wumboji
-    let mut wumboji = " wumboji";
+    let mut wumboji = true;
     // Don't do this if we've started recursion
     if first {
         // If there is any text other than whitespace, don't wumboji
         for expression in &ast {
             match expression {
-                Expression::CustomEmoji(_, _) => {}
+                Expression::CustomEmoji(_, _) | Expression::Emoji(_) => {}
                 Expression::Text(text) => {
                     if !text.chars().all(char::is_whitespace) {
-                        wumboji = "";
+                        wumboji = false;
                         break;
                     }
                 }
                 _ => {
-                    wumboji = "";
+                    wumboji = false;
                     break;
                 }
             }
         }
+    } else {
+        wumboji = false;
     }
     for expression in ast {
-        let html = match expression {
-            Expression::Text(text) => format!("{}", html_escape::encode_text(&text.to_string()).to_string()), // Escape HTML
-            Expression::CustomEmoji(name, id) => {
-                // Use user-provided callback to get emoji path
-                let path = (callbacks.emoji)(&id).0;
-                format!("<img src=\"{0}\" alt=\"{1}\" class=\"emoji{2}\" title=\"{1}\"></img>", path, name, wumboji)
-            }
-            // Expression::Emoji(emoji) => format!("<span class=\"emoji{}\">{}</span>", wumboji, emoji),
-            Expression::User(id) => format!("<span class=\"user\">@{}</span>", (callbacks.user)(id).0),
-            Expression::Role(id) => {
-                let (name, color) = (callbacks.role)(id);
-                format!(
-                    "<div class=\"role\" style=\"color: {0}\">@{1}<span style=\"background-color: {0}\"></span></div>",
-                    color.unwrap_or(String::from("#afafaf")),
-                    name,
-                )
-            },
-            Expression::Channel(id) => format!("<span class=\"channel\" data-id=\"{}\">#{}</span>", id, (callbacks.channel)(id).0),
-            Expression::Hyperlink(text, href) => format!("<a href=\"{}\" target=\"_blank\">{}</a>", href, text),
-            Expression::MultilineCode(text) => format!("<pre class=\"multiline_code\">{}</pre>", text.trim().replace("\n", "<br>")),
-            Expression::InlineCode(text) => format!("<span class=\"inline_code\">{}</span>", text.replace("\n", "<br>")),
-            Expression::Blockquote(a) => format!("<blockquote>{}</blockquote>", traverse(a, callbacks, false)),
-            Expression::Spoiler(a) => format!("<span class=\"spoiler\">{}</span>", traverse(a, callbacks, false)),
-            Expression::Underline(a) => format!("<u>{}</u>", traverse(a, callbacks, false)),
-            Expression::Strikethrough(a) => format!("<span class=\"strikethrough\">{}</span>", traverse(a, callbacks, false)),
-            Expression::Bold(a) => format!("<strong>{}</strong>", traverse(a, callbacks, false)),
-            Expression::Italics(a) => format!("<em>{}</em>", traverse(a, callbacks, false)),
-            Expression::Newline => String::from("<br>"),
+        let output = match expression {
+            Expression::Text(text) => renderer.text(text),
+            Expression::CustomEmoji(name, id) => renderer.custom_emoji(name, &id, wumboji),
+            Expression::Emoji(emoji) => renderer.emoji(emoji, wumboji),
+            Expression::User(id) => renderer.user(id),
+            Expression::Everyone => renderer.everyone(),
+            Expression::Here => renderer.here(),
+            Expression::Role(id) => renderer.role(id),
+            Expression::Channel(id) => renderer.channel(id),
+            Expression::GuildNavigation(kind) => renderer.guild_navigation(kind),
+            Expression::SlashCommand { name, subcommand_group, subcommand, id } => {
+                renderer.slash_command(name, subcommand_group, subcommand, id)
+            }
+            Expression::Timestamp { unix, style } => renderer.timestamp(unix, style),
+            Expression::MessageLink { guild, channel, message, suppress_embed } => {
+                renderer.message_link(guild, channel, message, suppress_embed)
+            }
+            Expression::Hyperlink { text, href, suppress_embed } => renderer.hyperlink(text, href, suppress_embed),
+            Expression::MaskedLink { content, href, suppress_embed } => {
+                renderer.masked_link(traverse(content, renderer, false), href, suppress_embed)
+            }
+            Expression::MultilineCode { language, code } => renderer.multiline_code(language, code),
+            Expression::InlineCode(code) => renderer.inline_code(code),
+            Expression::Blockquote(a) => renderer.blockquote(traverse(a, renderer, false)),
+            Expression::Heading(level, a) => renderer.heading(level, traverse(a, renderer, false)),
+            Expression::Subtext(a) => renderer.subtext(traverse(a, renderer, false)),
+            Expression::List { ordered, start, items } => renderer.list(
+                ordered,
+                start,
+                items.into_iter().map(|item| traverse(item, renderer, false)).collect(),
+            ),
+            Expression::Spoiler(a) => renderer.spoiler(traverse(a, renderer, false)),
+            Expression::Underline(a) => renderer.underline(traverse(a, renderer, false)),
+            Expression::Strikethrough(a) => renderer.strikethrough(traverse(a, renderer, false)),
+            Expression::Bold(a) => renderer.bold(traverse(a, renderer, false)),
+            Expression::Italics(a) => renderer.italics(traverse(a, renderer, false)),
+            Expression::Newline => renderer.newline(),
         };
-        final_html.push_str(&html);
+        final_output.push_str(&output);
     }
-    final_html
+    final_output
 }
 
 // Wrapper functions for traverse
@@ -98,17 +792,17 @@ fn traverse(ast: Vec<Expression>, callbacks: &Callbacks<impl Callback, impl Call
 /// );
 /// ```
 pub fn to_html(ast: Vec<Expression>) -> String {
-    traverse(ast, &Callbacks {
-        emoji: |x: &str| (x.to_owned(), None),
-        user: |x: &str| (x.to_owned(), None),
-        role: |x: &str| (x.to_owned(), None),
-        channel: |x: &str| (x.to_owned(), None),
-    }, true)
+    HtmlRenderer.render(ast)
 }
 
 /// Generates an HTML string from a vector of `Expression`s with callback functions for resolving
 /// custom emoji and user, role, and channel mentions
 ///
+/// This is a wrapper around `to_html_with_resolver`, which is easier to use correctly since each
+/// kind of mention has its own method and return type. Slash command mentions are shown with the
+/// name of the command and links to messages without a preview of the message, unless they're
+/// resolved with a `MentionResolver`.
+///
 /// The second value in the tuple is ignored for all callbacks except for `role`, so you can just
 /// supply `None`.
 ///
@@ -153,10 +847,625 @@ pub fn to_html_with_callbacks(
     role: impl Fn(&str) -> (String, Option<String>),
     channel: impl Fn(&str) -> (String, Option<String>),
 ) -> String {
-    traverse(ast, &Callbacks {
+    to_html_with_resolver(ast, &Callbacks {
+        emoji,
+        user,
+        role,
+        channel,
+    })
+}
+
+/// Generates an HTML string from a vector of `Expression`s, resolving custom emoji, mentions and
+/// links to messages with a `MentionResolver`
+///
+/// The avatar and color of users are only included in the HTML if the resolver gives them, and
+/// channels that aren't text channels get a `data-kind` attribute with the kind of channel.
+pub fn to_html_with_resolver(ast: Vec<Expression>, resolver: &impl MentionResolver) -> String {
+    ResolvedRenderer(resolver).render(ast)
+}
+
+/// Generates an HTML string from a vector of `Expression`s, resolving custom emoji and mentions
+/// with a single async call
+///
+/// The `MentionIds` in the AST are passed to `resolve`, which looks all of them up at once, like
+/// with one database query. This is only available with the `async` feature.
+///
+/// ```
+/// use discord_markdown::{parser::parse, convertor::*};
+///
+/// # futures::executor::block_on(async {
+/// let html = to_html_async(parse("<@111222333111222333> <@444555666444555666>"), |ids| async move {
+///     let mut resolved = ResolvedMentions::default();
+///     for id in ids.users {
+///         resolved.users.insert(id, UserInfo { name: "Jane Doe".to_owned(), ..UserInfo::default() });
+///     }
+///     resolved
+/// }).await;
+/// assert_eq!(html, "<span class=\"user\">@Jane Doe</span> <span class=\"user\">@Jane Doe</span>");
+/// # });
+/// ```
+#[cfg(feature = "async")]
+pub async fn to_html_async<F>(ast: Vec<Expression<'_>>, resolve: impl FnOnce(MentionIds) -> F) -> String
+where
+    F: Future<Output = ResolvedMentions>,
+{
+    let resolved = resolve(MentionIds::collect(&ast)).await;
+    to_html_with_resolver(ast, &resolved)
+}
+
+// Generates plain text from the AST, with relative timestamps relative to `now`
+fn traverse_plain_text(
+    ast: Vec<Expression>,
+    resolver: &impl MentionResolver,
+    mask_spoilers: bool,
+    now: i64,
+) -> String {
+    let mut final_text = String::new();
+    let count = ast.len();
+    for (i, expression) in ast.into_iter().enumerate() {
+        // Blockquotes, headings, subtext and lists take up whole lines, and the parser leaves out the
+        // newline after them
+        let end_of_line = if i + 1 < count { "\n" } else { "" };
+        let text = match expression {
+            Expression::Text(text) => text.to_string(),
+            Expression::CustomEmoji(name, _) => format!(":{}:", name),
+            Expression::Emoji(emoji) => emoji.to_string(),
+            Expression::User(id) => {
+                format!("@{}", resolver.user(id).map_or_else(|| UNKNOWN_USER.to_owned(), |user| user.name))
+            }
+            Expression::Everyone => String::from("@everyone"),
+            Expression::Here => String::from("@here"),
+            Expression::Role(id) => {
+                format!("@{}", resolver.role(id).map_or_else(|| DELETED_ROLE.to_owned(), |role| role.name))
+            }
+            Expression::Channel(id) => {
+                format!("#{}", resolver.channel(id).map_or_else(|| DELETED_CHANNEL.to_owned(), |channel| channel.name))
+            }
+            Expression::GuildNavigation(kind) => guild_navigation_name(kind).to_string(),
+            Expression::SlashCommand { name, subcommand_group, subcommand, id: _ } => {
+                format!("/{}", resolver.slash_command(&slash_command_words(name, subcommand_group, subcommand)))
+            }
+            Expression::Timestamp { unix, style } => format_timestamp(unix, style, now),
+            Expression::MessageLink { channel, message, .. } => {
+                let preview = message
+                    .and_then(|id| resolver.message(id))
+                    .map(|preview| format!(" › {}", preview))
+                    .unwrap_or_default();
+                let channel = resolver.channel(channel).map_or_else(|| DELETED_CHANNEL.to_owned(), |channel| channel.name);
+                format!("#{}{}", channel, preview)
+            }
+            Expression::Hyperlink { text, .. } => text.to_string(),
+            Expression::MultilineCode { code, .. } => code.to_string(),
+            Expression::InlineCode(code) => code.to_string(),
+            Expression::Blockquote(a) | Expression::Heading(_, a) | Expression::Subtext(a) => {
+                let text = traverse_plain_text(a, resolver, mask_spoilers, now);
+                // Like `> ` followed by an empty line, the content can already end the line
+                if text.ends_with('\n') { text } else { text + end_of_line }
+            }
+            Expression::List { ordered, start, items } => {
+                let items: Vec<_> = items
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, mut item)| {
+                        let marker = if ordered { format!("{}. ", start + i as u64) } else { String::from("- ") };
+                        // Nested lists are stored at the end of the item they belong to, and go on
+                        // the lines after it
+                        let content_len = item.len() - item.iter().rev().take_while(|e| matches!(e, Expression::List { .. })).count();
+                        let nested = item.split_off(content_len);
+                        let mut text = traverse_plain_text(item, resolver, mask_spoilers, now);
+                        for list in nested {
+                            text.push('\n');
+                            text.push_str(&traverse_plain_text(vec![list], resolver, mask_spoilers, now));
+                        }
+                        // Indent the lines of nested lists
+                        format!("{}{}", marker, text.replace('\n', &format!("\n{}", " ".repeat(marker.len()))))
+                    })
+                    .collect();
+                format!("{}{}", items.join("\n"), end_of_line)
+            }
+            Expression::Spoiler(_) if mask_spoilers => String::from("[spoiler]"),
+            Expression::MaskedLink { content: a, .. }
+            | Expression::Spoiler(a)
+            | Expression::Underline(a)
+            | Expression::Strikethrough(a)
+            | Expression::Bold(a)
+            | Expression::Italics(a) => traverse_plain_text(a, resolver, mask_spoilers, now),
+            Expression::Newline => String::from("\n"),
+        };
+        final_text.push_str(&text);
+    }
+    final_text
+}
+
+/// Generates plain text from a vector of `Expression`s, without any formatting
+///
+/// Code is kept as it is, and each line of blockquotes, headings, subtext and list items is put on
+/// a line of its own. If `mask_spoilers` is true, spoilers are replaced with `[spoiler]`.
+///
+/// Like `to_html`, this doesn't resolve mentions, so use `to_plain_text_with_callbacks` or
+/// `to_plain_text_with_resolver` if the input can contain them.
+///
+/// ```
+/// use discord_markdown::{parser::parse, convertor::to_plain_text};
+///
+/// let ast = parse("# Hi\n**Look** at `this`: ||secret||");
+/// assert_eq!(to_plain_text(ast, true), "Hi\nLook at this: [spoiler]");
+/// ```
+pub fn to_plain_text(ast: Vec<Expression>, mask_spoilers: bool) -> String {
+    traverse_plain_text(ast, &Ids, mask_spoilers, Ids.now())
+}
+
+/// Generates plain text from a vector of `Expression`s with the same callback functions as
+/// `to_html_with_callbacks`, as a wrapper around `to_plain_text_with_resolver`. See
+/// `to_plain_text` for how the text is generated.
+///
+/// Mentions are resolved to `@name` or `#name` with the first value of the output tuple of the
+/// callbacks, and custom emoji are shown as `:name:`, so the emoji callback isn't used.
+///
+/// ```
+/// use discord_markdown::{parser::parse, convertor::to_plain_text_with_callbacks};
+///
+/// let text = to_plain_text_with_callbacks(
+///     parse("_hey_ <@111222333111222333>, check <#333666999333666999>"),
+///     false,
+///     |name| (format!("/emojis/{}", name), None),
+///     |_| ("Jane Doe".to_owned(), None),
+///     |_| ("green".to_owned(), Some("#00ff00".to_owned())),
+///     |_| ("general".to_owned(), None),
+/// );
+/// assert_eq!(text, "hey @Jane Doe, check #general");
+/// ```
+pub fn to_plain_text_with_callbacks(
+    ast: Vec<Expression>,
+    mask_spoilers: bool,
+    emoji: impl Fn(&str) -> (String, Option<String>),
+    user: impl Fn(&str) -> (String, Option<String>),
+    role: impl Fn(&str) -> (String, Option<String>),
+    channel: impl Fn(&str) -> (String, Option<String>),
+) -> String {
+    to_plain_text_with_resolver(ast, mask_spoilers, &Callbacks {
         emoji,
         user,
         role,
         channel,
-    }, true)
+    })
+}
+
+/// Generates plain text from a vector of `Expression`s, resolving mentions and links to messages
+/// with a `MentionResolver`. See `to_plain_text` for how the text is generated.
+///
+/// Only the names of users, roles and channels are used, and custom emoji are shown as `:name:`.
+/// Relative timestamps are relative to `MentionResolver::now`.
+///
+/// ```
+/// use discord_markdown::{parser::parse, convertor::*};
+///
+/// struct Users;
+///
+/// impl MentionResolver for Users {
+///     fn user(&self, _: &str) -> Option<UserInfo> {
+///         Some(UserInfo { name: "Jane Doe".to_owned(), ..UserInfo::default() })
+///     }
+/// }
+///
+/// let text = to_plain_text_with_resolver(parse("_hey_ <@111222333111222333>"), false, &Users);
+/// assert_eq!(text, "hey @Jane Doe");
+/// ```
+pub fn to_plain_text_with_resolver(ast: Vec<Expression>, mask_spoilers: bool, resolver: &impl MentionResolver) -> String {
+    traverse_plain_text(ast, resolver, mask_spoilers, resolver.now())
+}
+
+// Whether a single character could start an expression, or be taken as part of one, if it isn't
+// escaped
+fn is_special(c: char) -> bool {
+    "\\*_~|`<>@:[#-".contains(c) || c.is_control() || (!c.is_ascii() && !c.is_alphanumeric())
+}
+
+// Whether the expression is text with a single character, which could be escaped
+fn is_char(expression: &Expression) -> bool {
+    matches!(expression, Expression::Text(text) if text.chars().count() == 1)
+}
+
+// Whether the expression takes up the rest of a line, which the parser stays at the start of a line
+// after
+fn is_block(expression: &Expression) -> bool {
+    matches!(
+        expression,
+        Expression::Blockquote(_) | Expression::Heading(_, _) | Expression::Subtext(_) | Expression::List { .. }
+    )
+}
+
+// Whether the markdown for a section of the AST will contain the delimiter, other than in italics
+// that could use the other one
+fn contains_delimiter(ast: &[Expression], delimiter: char) -> bool {
+    ast.iter().any(|expression| match expression {
+        Expression::Text(text) | Expression::InlineCode(text) | Expression::MultilineCode { code: text, .. } => {
+            text.contains(delimiter)
+        }
+        Expression::CustomEmoji(text, _) | Expression::Hyperlink { href: text, .. } => text.contains(delimiter),
+        Expression::MaskedLink { content, href, .. } => href.contains(delimiter) || contains_delimiter(content, delimiter),
+        Expression::Bold(a) => delimiter == '*' || contains_delimiter(a, delimiter),
+        Expression::Underline(a) => delimiter == '_' || contains_delimiter(a, delimiter),
+        Expression::Blockquote(a)
+        | Expression::Heading(_, a)
+        | Expression::Subtext(a)
+        | Expression::Spoiler(a)
+        | Expression::Strikethrough(a)
+        | Expression::Italics(a) => contains_delimiter(a, delimiter),
+        Expression::List { items, .. } => items.iter().any(|item| contains_delimiter(item, delimiter)),
+        _ => false,
+    })
+}
+
+// Whether the markdown for a section of the AST will start with a space
+fn starts_with_space(ast: &[Expression]) -> bool {
+    matches!(ast.first(), Some(Expression::Text(text)) if text.starts_with(' '))
+}
+
+// Italics delimiters in text that's written as it is, which would start italics if the same
+// delimiter came later. The parser looks for the end of italics anywhere after the start, even in
+// expressions that are nested in others, so these carry on into nested sections.
+#[derive(Clone, Copy, Default)]
+struct OpenDelimiters {
+    asterisk: bool,
+    underscore: bool,
+    // The delimiter of italics that came right after the same one, which would start bold text or an
+    // underline ended by the next two in a row
+    doubled: Option<char>,
+}
+
+impl OpenDelimiters {
+    fn contains(self, delimiter: char) -> bool {
+        match delimiter {
+            '*' => self.asterisk,
+            '_' => self.underscore,
+            _ => false,
+        }
+    }
+
+    fn insert(&mut self, delimiter: char) {
+        match delimiter {
+            '*' => self.asterisk = true,
+            '_' => self.underscore = true,
+            _ => {}
+        }
+    }
+
+    // Adds the delimiters in text that's written as it is. Delimiters followed by the same one
+    // don't start italics there, and shrugs are never parsed as anything else.
+    fn insert_text(&mut self, text: &str) {
+        if text == r"¯\_(ツ)_/¯" {
+            return;
+        }
+        for delimiter in ['*', '_'] {
+            if text.as_bytes().windows(2).any(|w| w[0] == delimiter as u8 && w[1] != delimiter as u8) {
+                self.insert(delimiter);
+            }
+        }
+    }
+
+    // Adds the delimiter at the end of the text before an expression, which only starts italics if
+    // the expression doesn't start with the same delimiter
+    fn after_text(mut self, text_before: &str, start: char) -> Self {
+        if let Some(delimiter) = text_before.chars().last().filter(|&c| c != start) {
+            self.insert(delimiter);
+        }
+        self
+    }
+}
+
+// Whether a `Text` has to be escaped. The parser only splits text where another expression starts
+// or where a character is escaped, so longer text is written as it is, and single characters are
+// escaped if they could start an expression or would be joined to the text next to them.
+fn escape_text(text: &str, previous: Option<&Expression>, rest: &[Expression], after_text: bool) -> bool {
+    let mut chars = text.chars();
+    match (chars.next(), chars.next()) {
+        // A backslash right after a link would be taken as part of the URL
+        (Some(c), None) => {
+            !matches!(previous, Some(Expression::Hyperlink { suppress_embed: false, .. } | Expression::MessageLink { suppress_embed: false, .. }))
+                && (after_text
+                    || is_special(c)
+                    || matches!(rest.first(), Some(Expression::Text(_)) if !rest.first().is_some_and(is_char)))
+        }
+        _ => false,
+    }
+}
+
+// Surrounds the content of bold text or an underline with two delimiters on each side. For content
+// ending with a delimiter, like `***foo***`, the parser looks for three delimiters in a row anywhere
+// after the start. Otherwise, it looks for the first two in a row, so four delimiters are used on
+// each side if the content has two in a row, or if there are three in a row later on.
+fn double_delimit(content: &str, delimiter: char, after: &str) -> String {
+    let double = delimiter.to_string().repeat(2);
+    let four = content.is_empty()
+        || (!content.ends_with(delimiter)
+            && (content.contains(&double)
+                || format!("{}{}", double, after).contains(&delimiter.to_string().repeat(3))));
+    let delimiter = delimiter.to_string().repeat(if four { 4 } else { 2 });
+    format!("{}{}{}", delimiter, content, delimiter)
+}
+
+// Generates the markdown for the items of a list, one line per item, with nested lists indented
+// further
+fn list_to_markdown(ordered: bool, start: u64, items: &[Vec<Expression>], indent: usize, open: OpenDelimiters) -> String {
+    let mut lines = Vec::new();
+    for (i, item) in items.iter().enumerate() {
+        // Nested lists are stored at the end of the item they belong to
+        let content_len = item.len() - item.iter().rev().take_while(|e| matches!(e, Expression::List { .. })).count();
+        let marker = if ordered { format!("{}. ", start + i as u64) } else { String::from("- ") };
+        lines.push(format!("{}{}{}", " ".repeat(indent), marker, markdown_section(&item[..content_len], false, open)));
+        let nested = &item[content_len..];
+        for (j, list) in nested.iter().enumerate() {
+            if let Expression::List { ordered, start, items } = list {
+                // Like lists at the top level, a nested list has to be indented further than a
+                // nested list of the same kind after it for them to be separate
+                let following = nested[j + 1..]
+                    .iter()
+                    .take_while(|e| matches!(e, Expression::List { ordered: o, .. } if o == ordered))
+                    .count();
+                lines.push(list_to_markdown(*ordered, *start, items, indent + marker.len() + 2 * following, open));
+            }
+        }
+    }
+    lines.join("\n")
+}
+
+// What the markdown for an expression depends on, other than the expression itself
+struct Context<'a, 'b> {
+    // The expression before it in its section
+    previous: Option<&'a Expression<'b>>,
+    // The expressions after it in its section
+    rest: &'a [Expression<'b>],
+    // The text right before it if that text isn't escaped, or an empty string
+    text_before: &'b str,
+    // Whether a list item could start at the expression, because it's at the start of a line or only
+    // has spaces before it there
+    list_start: bool,
+    // Whether the expression is text that has to be escaped
+    escape: bool,
+    // The delimiter used for italics right before it
+    italics_before: Option<char>,
+    open: OpenDelimiters,
+}
+
+// Generates the markdown for an expression. For bold text and underlines, this is only the content,
+// because their delimiters depend on the markdown after them.
+fn expression_to_markdown(expression: &Expression, context: Context) -> String {
+    let Context { previous, rest, text_before, list_start, escape, italics_before, open } = context;
+    // Expressions that take up the rest of a line have to be followed by a newline, unless they are
+    // at the end of their section
+    let end_of_line = if rest.is_empty() { "" } else { "\n" };
+    // Generates the markdown for a nested section, given the character the expression starts with
+    let section = |a: &[Expression], start: char| markdown_section(a, false, open.after_text(text_before, start));
+    match expression {
+        Expression::Text(text) if escape => format!("\\{}", text),
+        Expression::Text(text) => text.to_string(),
+        Expression::CustomEmoji(name, id) => {
+            let (id, extension) = id.split_once('.').unwrap_or((id, "png"));
+            format!("<{}:{}:{}>", if extension == "gif" { "a" } else { "" }, name, id)
+        }
+        Expression::Emoji(emoji) => emoji.to_string(),
+        Expression::User(id) => format!("<@{}>", id),
+        Expression::Role(id) => format!("<@&{}>", id),
+        Expression::Everyone => String::from("@everyone"),
+        Expression::Here => String::from("@here"),
+        Expression::Channel(id) => format!("<#{}>", id),
+        Expression::GuildNavigation(kind) => match kind {
+            GuildNavigationKind::LinkedRoles(Some(id)) => format!("<id:{}:{}>", kind.as_str(), id),
+            _ => format!("<id:{}>", kind.as_str()),
+        },
+        Expression::SlashCommand { name, subcommand_group, subcommand, id } => {
+            let command: Vec<_> = [Some(name), subcommand_group.as_ref(), subcommand.as_ref()].iter().flatten().copied().copied().collect();
+            format!("</{}:{}>", command.join(" "), id)
+        }
+        Expression::Timestamp { unix, style } if *style == TimestampStyle::default() => format!("<t:{}>", unix),
+        Expression::Timestamp { unix, style } => format!("<t:{}:{}>", unix, style.as_char()),
+        Expression::MessageLink { guild, channel, message, suppress_embed } => {
+            let link = format!(
+                "https://discord.com/channels/{}/{}{}",
+                guild,
+                channel,
+                message.map(|id| format!("/{}", id)).unwrap_or_default(),
+            );
+            if *suppress_embed { format!("<{}>", link) } else { link }
+        }
+        Expression::Hyperlink { text, href, suppress_embed } => {
+            let link = if *suppress_embed { format!("<{}>", href) } else { href.to_string() };
+            if text == href {
+                link
+            } else {
+                format!("[{}]({})", text, link)
+            }
+        }
+        Expression::MaskedLink { content, href, suppress_embed } => {
+            let href = if *suppress_embed { format!("<{}>", href) } else { href.to_string() };
+            format!("[{}]({})", section(content, '['), href)
+        }
+        Expression::MultilineCode { language, code } => {
+            let language = match language {
+                Some(language) => format!("{}\n", language),
+                // Without a newline first, the first line of the code would be taken as its language
+                None if code.split_once('\n').is_some_and(|(line, _)| is_language_tag(line)) => String::from("\n"),
+                None => String::new(),
+            };
+            // A backtick at the end of the code would be taken as part of the closing delimiter,
+            // and the parser leaves out newlines around the code
+            let end = if code.ends_with('`') { "\n" } else { "" };
+            format!("```{}{}{}```", language, code, end)
+        }
+        Expression::InlineCode(code) if code.is_empty() || code.contains('`') => format!("``{}``", code),
+        Expression::InlineCode(code) => format!("`{}`", code),
+        Expression::Blockquote(a) => match markdown_section(a, true, open.after_text(text_before, '>')) {
+            // `> ` followed by an empty line is a blockquote that includes the newline
+            content if content == "\n" => String::from("> \n"),
+            // Blockquotes over more than one line go on until the end of the message
+            content if content.contains('\n') => format!(">>> {}", content),
+            content => format!("> {}{}", content, end_of_line),
+        },
+        Expression::Heading(level, a) => format!("{} {}{}", "#".repeat(*level as usize), section(a, '#'), end_of_line),
+        Expression::Subtext(a) => format!("-# {}{}", section(a, '-'), end_of_line),
+        Expression::List { ordered, start, items } => {
+            // A list has to be indented further than a list of the same kind after it for them to
+            // be separate
+            let following = rest
+                .iter()
+                .take_while(|e| matches!(e, Expression::List { ordered: o, .. } if o == ordered))
+                .count();
+            let open = open.after_text(text_before, ' ');
+            format!("{}{}", list_to_markdown(*ordered, *start, items, 2 * following, open), end_of_line)
+        }
+        Expression::Spoiler(a) => format!("||{}||", section(a, '|')),
+        Expression::Underline(a) => section(a, '_'),
+        Expression::Strikethrough(a) => format!("~~{}~~", section(a, '~')),
+        Expression::Bold(a) => section(a, '*'),
+        Expression::Italics(a) => {
+            // Italics can't contain their own delimiter. Otherwise, a delimiter is avoided if it
+            // would end italics starting earlier, or be taken as part of bold text or an underline
+            // right before it, including ones started by italics that came after the same delimiter,
+            // and at the start of a line, `* ` starts a list. A delimiter right after the same one
+            // at the end of the text before can't end italics there though.
+            let avoid = |delimiter: char| {
+                !text_before.ends_with(delimiter)
+                    && (open.contains(delimiter)
+                        || matches!((delimiter, previous), ('*', Some(Expression::Bold(_))) | ('_', Some(Expression::Underline(_))))
+                        || (open.doubled == Some(delimiter) && italics_before == Some(delimiter))
+                        || (delimiter == '*' && list_start && starts_with_space(a)))
+            };
+            // Delimiters at the end of the text before would start italics there unless these
+            // italics use the same one, but four in a row would be empty bold text or an underline,
+            // and two in a row would end bold text or an underline started like that earlier
+            let last = text_before.chars().last().filter(|c| *c == '*' || *c == '_');
+            let run = last.map_or(0, |last| text_before.chars().rev().take_while(|c| *c == last).count());
+            let other = |c: char| if c == '*' { '_' } else { '*' };
+            let underscores = match last {
+                Some(c) if run < 3 && open.doubled != Some(c) && !contains_delimiter(a, c) => c == '_',
+                Some(c) if run >= 3 && !contains_delimiter(a, other(c)) => c == '*',
+                // If both are avoided, an underline right before can still take one more
+                _ => {
+                    contains_delimiter(a, '*')
+                        || (avoid('*')
+                            && (!avoid('_') || matches!(previous, Some(Expression::Underline(_))))
+                            && !contains_delimiter(a, '_'))
+                }
+            };
+            let delimiter = if underscores { '_' } else { '*' };
+            // Italics nested in these have to use the other delimiter
+            let mut open = open.after_text(text_before, delimiter);
+            open.insert(delimiter);
+            match a.split_first() {
+                // `* ` would start a list, so an escaped space has to stay escaped
+                Some((Expression::Text(" "), rest)) if delimiter == '*' && list_start => {
+                    format!("*\\ {}*", markdown_section(rest, false, open))
+                }
+                _ => format!("{0}{1}{0}", delimiter, markdown_section(a, false, open)),
+            }
+        }
+        Expression::Newline => String::from("\n"),
+    }
+}
+
+// Generates the markdown for a section of the AST, which the parser starts at the start of a line if
+// `line_start` is true. Expressions mostly run into the ones next to them, so the delimiters and
+// escapes for each expression are chosen based on those, and on the delimiters that are still open
+// from earlier.
+fn markdown_section(ast: &[Expression], mut line_start: bool, mut open: OpenDelimiters) -> String {
+    let nested = !line_start;
+    // The markdown for each expression, with the delimiter for bold text and underlines, which is
+    // added once the markdown after them is known
+    let mut outputs = Vec::with_capacity(ast.len());
+    let mut text_before = "";
+    // Whether the parser was at the start of a line before `text_before`
+    let mut text_line_start = false;
+    let mut italics_before = None;
+    for (i, expression) in ast.iter().enumerate() {
+        let previous = i.checked_sub(1).map(|i| &ast[i]);
+        let rest = &ast[i + 1..];
+        let escape = match expression {
+            Expression::Text(text) => escape_text(text, previous, rest, !text_before.is_empty()),
+            _ => false,
+        };
+        // List items can be indented
+        let list_start = line_start
+            || (text_line_start && !text_before.is_empty() && text_before.bytes().all(|c| c == b' '));
+        let context = Context { previous, rest, text_before, list_start, escape, italics_before, open };
+        let output = expression_to_markdown(expression, context);
+        let delimiter = match expression {
+            Expression::Bold(_) => Some('*'),
+            Expression::Underline(_) => Some('_'),
+            _ => None,
+        };
+        open = open.after_text(text_before, delimiter.or_else(|| output.chars().next()).unwrap_or(' '));
+        text_line_start = line_start;
+        if let Expression::Italics(_) = expression {
+            open.doubled = open.doubled.or(output.chars().next().filter(|&c| text_before.ends_with(c)));
+        }
+        italics_before = match expression {
+            Expression::Italics(_) => output.chars().last(),
+            _ => None,
+        };
+        line_start = match expression {
+            // Shrugs don't change whether the parser is at the start of a line
+            Expression::Text(text) => line_start && *text == r"¯\_(ツ)_/¯",
+            Expression::Newline => true,
+            expression => line_start && is_block(expression),
+        };
+        text_before = match expression {
+            Expression::Text(text) if !escape => {
+                open.insert_text(text);
+                text
+            }
+            _ => "",
+        };
+        outputs.push((output, delimiter));
+    }
+    // Bold text and underlines are delimited based on the markdown after them, so this goes from
+    // the end
+    let mut markdown = String::new();
+    for (output, delimiter) in outputs.into_iter().rev() {
+        let output = match delimiter {
+            Some(delimiter) => double_delimit(&output, delimiter, &markdown),
+            None => output,
+        };
+        markdown.insert_str(0, &output);
+    }
+    // Expressions other than blockquotes that take up the rest of a line would run into the delimiter
+    // that ends the section
+    if nested && ast.last().is_some_and(|e| is_block(e) && !matches!(e, Expression::Blockquote(_))) {
+        markdown.push('\n');
+    }
+    markdown
+}
+
+/// Generates a markdown string from a vector of `Expression`s, which gives back the same vector
+/// when parsed with `parser::parse`, or with `parser::parse_with_options` using the options the
+/// AST was parsed with
+///
+/// Delimiters and escapes are chosen so that nested expressions like `Bold(vec![Italics(..)])`
+/// parse correctly. The AST doesn't store everything about the original input, so the markdown
+/// could differ from it, like using `*` for italics that were written with `_`. If the AST isn't
+/// one the parser can generate, like if it has two `Text`s next to each other, the output is as
+/// close to it as possible.
+///
+/// ```
+/// use discord_markdown::{parser::{parse, Expression::*}, convertor::to_markdown};
+///
+/// let ast = vec![
+///     Text("hi "),
+///     Bold(vec![Italics(vec![Text("there")])]),
+///     Text("*"),
+///     Newline,
+///     Heading(1, vec![Underline(vec![Text("foo")])]),
+/// ];
+/// let markdown = to_markdown(ast);
+/// assert_eq!(markdown, "hi ***there***\\*\n# __foo__");
+/// assert_eq!(parse(&markdown), vec![
+///     Text("hi "),
+///     Bold(vec![Italics(vec![Text("there")])]),
+///     Text("*"),
+///     Newline,
+///     Heading(1, vec![Underline(vec![Text("foo")])]),
+/// ]);
+/// ```
+pub fn to_markdown(ast: Vec<Expression>) -> String {
+    markdown_section(&ast, true, OpenDelimiters::default())
 }
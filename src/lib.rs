@@ -6,12 +6,45 @@
 //! Call `parser::parse` on the input string, and it will return a vector of `Expression`s. Supply
 //! this vector to `convertor::to_html` to get an HTML string. The text in the generated HTML will
 //! be HTML-escaped, so you can safely insert the output into the DOM. If your input text will also
-//! have custom emoji, user mentions, role mentions, or channel mentions, then use
-//! `convertor::to_html_with_callbacks` instead.
+//! have custom emoji, user mentions, role mentions, channel mentions, slash command mentions, or
+//! links to messages, then implement `convertor::MentionResolver` to look them up and use
+//! `convertor::to_html_with_resolver` instead. For custom emoji and user, role and channel
+//! mentions, you can also pass callback functions to `convertor::to_html_with_callbacks`.
+//!
+//! If looking them up is async, like with a database, enable the `async` feature and use
+//! `convertor::to_html_async`, which resolves all the mentions in the AST with one call.
+//!
+//! To change how some expressions are rendered, implement `convertor::Renderer`, which generates the
+//! same HTML as `to_html` for every method you don't implement, and call `Renderer::render`. To
+//! still resolve mentions, call the methods of `convertor::ResolvedRenderer` from yours.
+//!
+//! For a plain text version of a message without any formatting, like for notifications, use
+//! `convertor::to_plain_text`, or `convertor::to_plain_text_with_callbacks` and
+//! `convertor::to_plain_text_with_resolver` to resolve mentions.
 //!
 //! Call `parser::parse_with_md_hyperlinks` instead if you want to also parse links with alt text,
 //! which is supported in discord embeds (Like `[example](https://example.com)`)
 //!
+//! Use `parser::parse_with_options` to enable other rules that are off by default, like converting
+//! emoji shortcodes (`:thumbsup:`) into unicode emoji, or parsing links with alt text with the
+//! restrictions discord has for them in messages.
+//!
+//! If you need to know where each expression came from in the input, like for highlighting, use
+//! `parser::parse_spanned`, which also returns the byte range of every expression and its content.
+//!
+//! To find the users, roles, channels, custom emoji, URLs and messages a message references, and
+//! whether it mentions `@everyone` or `@here`, like for notifications, call `parser::references`
+//! on the AST.
+//!
+//! The AST borrows from the input string. Call `Expression::into_owned` to get an `OwnedExpression`
+//! that can be stored after the input is dropped, and `OwnedExpression::as_expression` to convert it.
+//!
+//! Enable the `serde` feature to serialize the AST, and deserialize `OwnedExpression`s. The format is
+//! documented on `parser::Expression`.
+//!
+//! To go back from the AST to markdown, like after editing it, use `convertor::to_markdown`. The
+//! generated markdown parses back into the same AST.
+//!
 //! ### Note:
 //! Newlines are not converted to `Expression::Newline` inside code blocks, so that must be handled
 //! in the covertor.
@@ -96,7 +129,16 @@ pub mod convertor;
 
 #[cfg(test)]
 mod tests {
-    use super::parser::{parse, Expression::*};
+    use super::parser::{
+        parse, parse_spanned, parse_with_options, references, CustomEmojiReference, Expression,
+        Expression::*, GuildNavigationKind, MaskedLinks, MessageLinkReference, OwnedExpression, ParseOptions,
+        References, Span, TimestampStyle,
+    };
+    use std::ops::Range;
+
+    fn span(range: Range<usize>, content: Range<usize>, children: Vec<Span>) -> Span {
+        Span { range, content, children }
+    }
 
     #[test]
     fn parser_basic() {
@@ -111,10 +153,135 @@ mod tests {
         assert_eq!(parse("`foo` ``foo ` bar``"), vec![
             InlineCode("foo"), Text(" "), InlineCode("foo ` bar")
         ]);
-        assert_eq!(parse("```foo\nbar```"), vec![MultilineCode("foo\nbar")]);
+        assert_eq!(parse("```foo\nbar```"), vec![
+            MultilineCode { language: Some("foo"), code: "bar" }
+        ]);
         assert_eq!(parse("> foo bar"), vec![Blockquote(vec![Text("foo bar")])]);
     }
 
+    #[test]
+    fn parse_code_block_languages() {
+        assert_eq!(parse("```rust\nfn main() {}\n```"), vec![
+            MultilineCode { language: Some("rust"), code: "fn main() {}" }
+        ]);
+        assert_eq!(parse("```c++\n\n  foo\n\n```"), vec![
+            MultilineCode { language: Some("c++"), code: "  foo" }
+        ]);
+        // The first line isn't a language if nothing follows it or it isn't a single word
+        assert_eq!(parse("```rust\n```"), vec![
+            MultilineCode { language: None, code: "rust" }
+        ]);
+        assert_eq!(parse("```rust```"), vec![
+            MultilineCode { language: None, code: "rust" }
+        ]);
+        assert_eq!(parse("```foo bar\nbaz```"), vec![
+            MultilineCode { language: None, code: "foo bar\nbaz" }
+        ]);
+        assert_eq!(parse("```\nfoo```"), vec![
+            MultilineCode { language: None, code: "foo" }
+        ]);
+    }
+
+    #[test]
+    fn parse_multiline_blockquotes() {
+        assert_eq!(parse("foo\n>>> **bar**\n# baz\n> qux"), vec![
+            Text("foo"),
+            Newline,
+            Blockquote(vec![
+                Bold(vec![Text("bar")]),
+                Newline,
+                Heading(1, vec![Text("baz")]),
+                Text("> qux"),
+            ]),
+        ]);
+        assert_eq!(parse(">>> \nfoo\n\nbar"), vec![
+            Blockquote(vec![Newline, Text("foo"), Newline, Newline, Text("bar")]),
+        ]);
+        assert_eq!(parse("foo >>> bar"), vec![Text("foo >>> bar")]);
+        assert_eq!(parse(">>>foo"), vec![Text(">>>foo")]);
+    }
+
+    #[test]
+    fn parse_headings() {
+        assert_eq!(parse("# foo\n## **bar**\n### baz"), vec![
+            Heading(1, vec![Text("foo")]),
+            Heading(2, vec![Bold(vec![Text("bar")])]),
+            Heading(3, vec![Text("baz")]),
+        ]);
+        // Headings are only recognized at the start of a line
+        assert_eq!(parse("foo # bar"), vec![Text("foo # bar")]);
+        assert_eq!(parse("#### foo"), vec![Text("#### foo")]);
+        assert_eq!(parse("#foo"), vec![Text("#foo")]);
+        // The content of a blockquote is at the start of a line
+        assert_eq!(parse("> # foo\n> bar # baz"), vec![
+            Blockquote(vec![Heading(1, vec![Text("foo")])]),
+            Blockquote(vec![Text("bar # baz")]),
+        ]);
+        assert_eq!(parse("> > # foo"), vec![Blockquote(vec![Text("> # foo")])]);
+        // Escaped characters aren't at the start of a line either
+        assert_eq!(parse("\\a# b\n\\a-# b\n\\a- b"), vec![
+            Text("a"), Text("# b"), Newline, Text("a"), Text("-# b"), Newline, Text("a"), Text("- b"),
+        ]);
+    }
+
+    #[test]
+    fn parse_subtext() {
+        assert_eq!(parse("-# foo *bar*\n-# baz"), vec![
+            Subtext(vec![Text("foo "), Italics(vec![Text("bar")])]),
+            Subtext(vec![Text("baz")]),
+        ]);
+        assert_eq!(parse("foo -# bar"), vec![Text("foo -# bar")]);
+        assert_eq!(parse("-#foo"), vec![Text("-#foo")]);
+    }
+
+    #[test]
+    fn parse_lists() {
+        assert_eq!(parse("- foo\n* **bar**\nbaz"), vec![
+            List {
+                ordered: false,
+                start: 1,
+                items: vec![vec![Text("foo")], vec![Bold(vec![Text("bar")])]],
+            },
+            Text("baz"),
+        ]);
+        assert_eq!(parse("3. foo\n  - bar\n    1. baz\n  - qux\n4. quux"), vec![
+            List {
+                ordered: true,
+                start: 3,
+                items: vec![
+                    vec![
+                        Text("foo"),
+                        List {
+                            ordered: false,
+                            start: 1,
+                            items: vec![
+                                vec![
+                                    Text("bar"),
+                                    List { ordered: true, start: 1, items: vec![vec![Text("baz")]] },
+                                ],
+                                vec![Text("qux")],
+                            ],
+                        },
+                    ],
+                    vec![Text("quux")],
+                ],
+            },
+        ]);
+        // Changing the kind of list starts a new list
+        assert_eq!(parse("- foo\n1. bar"), vec![
+            List { ordered: false, start: 1, items: vec![vec![Text("foo")]] },
+            List { ordered: true, start: 1, items: vec![vec![Text("bar")]] },
+        ]);
+        assert_eq!(parse("*foo* - bar"), vec![Italics(vec![Text("foo")]), Text(" - bar")]);
+        assert_eq!(parse("1.foo"), vec![Text("1.foo")]);
+        // Lists can be quoted, one line at a time or in a multi-line blockquote
+        assert_eq!(parse("> - foo\n> 2. bar\n>>> - baz\n- qux"), vec![
+            Blockquote(vec![List { ordered: false, start: 1, items: vec![vec![Text("foo")]] }]),
+            Blockquote(vec![List { ordered: true, start: 2, items: vec![vec![Text("bar")]] }]),
+            Blockquote(vec![List { ordered: false, start: 1, items: vec![vec![Text("baz")], vec![Text("qux")]] }]),
+        ]);
+    }
+
     #[test]
     fn parse_regex() {
         // Tests the parsers that use regex
@@ -129,4 +296,352 @@ mod tests {
             CustomEmoji("foo", "123456789123456789.png".to_owned()),
         ]);
     }
+
+    #[test]
+    fn parse_emoji() {
+        assert_eq!(parse("hi 🎉🎉"), vec![Text("hi "), Emoji("🎉"), Emoji("🎉")]);
+        // ZWJ sequences, skin tones, flags, and keycaps
+        assert_eq!(parse("👨‍👩‍👧👍🏽🇺🇸1️⃣"), vec![
+            Emoji("👨‍👩‍👧"),
+            Emoji("👍🏽"),
+            Emoji("🇺🇸"),
+            Emoji("1️⃣"),
+        ]);
+        assert_eq!(parse("**❤️** © 2021"), vec![Bold(vec![Emoji("❤️")]), Text(" © 2021")]);
+    }
+
+    #[test]
+    fn parse_emoji_shortcodes() {
+        let options = ParseOptions { emoji_shortcodes: true, ..ParseOptions::default() };
+        assert_eq!(parse_with_options(":thumbsup::+1: __:tada:__", &options), vec![
+            Emoji("👍"),
+            Emoji("👍"),
+            Text(" "),
+            Underline(vec![Emoji("🎉")]),
+        ]);
+        assert_eq!(parse_with_options(":thumbsup_tone3: :flag_us: :flag_zz:", &options), vec![
+            Emoji("👍🏽"),
+            Text(" "),
+            Emoji("🇺🇸"),
+            Text(" :flag_zz:"),
+        ]);
+        // Discord's names are used where they're different from gemoji's
+        assert_eq!(
+            parse_with_options(
+                ":slight_smile::upside_down::hugging::nerd::zipper_mouth::slight_frown::regional_indicator_a::flag_uk:",
+                &options,
+            ),
+            Vec::from(["🙂", "🙃", "🤗", "🤓", "🤐", "🙁", "🇦", "🇬🇧"].map(Emoji)),
+        );
+        assert_eq!(parse_with_options(":wave::skin-tone-3: :hand_splayed_tone2: :skin-tone-6:", &options), vec![
+            Emoji("👋"),
+            Emoji("🏽"),
+            Text(" "),
+            Emoji("🖐🏼"),
+            Text(" :skin-tone-6:"),
+        ]);
+        assert_eq!(parse_with_options(r":foo: \:tada:", &options), vec![
+            Text(":foo: "),
+            Text(":"),
+            Text("tada:"),
+        ]);
+        // Shortcodes aren't converted unless the option is enabled
+        assert_eq!(parse(":tada:"), vec![Text(":tada:")]);
+    }
+
+    #[test]
+    fn parse_everyone_here() {
+        assert_eq!(parse("@everyone hi@here"), vec![Everyone, Text(" hi"), Here]);
+        assert_eq!(parse(r"\@everyone @her"), vec![Text("@"), Text("everyone @her")]);
+    }
+
+    #[test]
+    fn parse_guild_navigation() {
+        assert_eq!(parse("<id:customize><id:browse><id:guide><id:home>"), vec![
+            GuildNavigation(GuildNavigationKind::Customize),
+            GuildNavigation(GuildNavigationKind::Browse),
+            GuildNavigation(GuildNavigationKind::Guide),
+            GuildNavigation(GuildNavigationKind::Home),
+        ]);
+        assert_eq!(parse("<id:linked-roles><id:linked-roles:1234>"), vec![
+            GuildNavigation(GuildNavigationKind::LinkedRoles(None)),
+            GuildNavigation(GuildNavigationKind::LinkedRoles(Some("1234"))),
+        ]);
+        assert_eq!(parse("<id:foo>"), vec![Text("<id:foo>")]);
+        // Only linked roles can have an ID
+        assert_eq!(parse("<id:browse:123><id:customize:5>"), vec![Text("<id:browse:123><id:customize:5>")]);
+    }
+
+    #[test]
+    fn parse_slash_commands() {
+        assert_eq!(parse("</ping:1234> </settings color:1234> </settings user color:1234>"), vec![
+            SlashCommand { name: "ping", subcommand_group: None, subcommand: None, id: "1234" },
+            Text(" "),
+            SlashCommand { name: "settings", subcommand_group: None, subcommand: Some("color"), id: "1234" },
+            Text(" "),
+            SlashCommand {
+                name: "settings",
+                subcommand_group: Some("user"),
+                subcommand: Some("color"),
+                id: "1234",
+            },
+        ]);
+        assert_eq!(parse("</a b c d:1234>"), vec![Text("</a b c d:1234>")]);
+        let long_name = format!("</{}:1234>", "a".repeat(33));
+        assert_eq!(parse(&long_name), vec![Text(&long_name)]);
+    }
+
+    // Shorthand for a hyperlink without alt text
+    fn link(href: &str) -> Expression<'_> {
+        Hyperlink { text: href, href, suppress_embed: false }
+    }
+
+    #[test]
+    fn parse_urls() {
+        assert_eq!(parse("steam://run/440 mailto:foo@example.com https://例子.测试/路径"), vec![
+            link("steam://run/440"),
+            Text(" "),
+            link("mailto:foo@example.com"),
+            Text(" "),
+            link("https://例子.测试/路径"),
+        ]);
+        // Balanced brackets are part of the URL, and trailing punctuation isn't
+        assert_eq!(parse("https://en.wikipedia.org/wiki/Rust_(programming_language)."), vec![
+            link("https://en.wikipedia.org/wiki/Rust_(programming_language)"),
+            Text("."),
+        ]);
+        assert_eq!(parse("(see HTTPS://example.com/?q=[a]), or <https://example.com/a.>"), vec![
+            Text("(see "),
+            link("HTTPS://example.com/?q=[a]"),
+            Text("), or "),
+            Hyperlink { text: "https://example.com/a.", href: "https://example.com/a.", suppress_embed: true },
+        ]);
+        assert_eq!(parse("https:// note: foo"), vec![Text("https:// note: foo")]);
+        // Schemes like https have to be followed by `://`
+        assert_eq!(parse("https:a HTTP:/a steam:run"), vec![Text("https:a HTTP:/a steam:run")]);
+        let options = ParseOptions { link_schemes: vec!["https".to_owned()], ..ParseOptions::default() };
+        assert_eq!(parse_with_options("http://example.com https://example.com", &options), vec![
+            Text("http://example.com "),
+            link("https://example.com"),
+        ]);
+    }
+
+    #[test]
+    fn parse_message_links() {
+        assert_eq!(parse("https://discord.com/channels/1234/5678/9012 https://canary.discordapp.com/channels/@me/5678/"), vec![
+            MessageLink { guild: "1234", channel: "5678", message: Some("9012"), suppress_embed: false },
+            Text(" "),
+            MessageLink { guild: "@me", channel: "5678", message: None, suppress_embed: false },
+        ]);
+        assert_eq!(parse("<https://discord.com/channels/1234/5678>"), vec![
+            MessageLink { guild: "1234", channel: "5678", message: None, suppress_embed: true },
+        ]);
+        assert_eq!(parse("HTTPS://discord.com/channels/1234/5678 https://PTB.Discord.com/channels/1234/5678"), vec![
+            MessageLink { guild: "1234", channel: "5678", message: None, suppress_embed: false },
+            Text(" "),
+            MessageLink { guild: "1234", channel: "5678", message: None, suppress_embed: false },
+        ]);
+        assert_eq!(parse("https://discord.com/channels/1234/5678/9012/foo"), vec![
+            link("https://discord.com/channels/1234/5678/9012/foo"),
+        ]);
+    }
+
+    #[test]
+    fn parse_suppressed_embeds() {
+        assert_eq!(parse("<https://example.com> https://example.com"), vec![
+            Hyperlink { text: "https://example.com", href: "https://example.com", suppress_embed: true },
+            Text(" "),
+            Hyperlink { text: "https://example.com", href: "https://example.com", suppress_embed: false },
+        ]);
+    }
+
+    #[test]
+    fn parse_masked_links() {
+        let restricted = ParseOptions { masked_links: MaskedLinks::Restricted, ..ParseOptions::default() };
+        assert_eq!(parse_with_options("**[docs](https://docs.rs)** [foo](<http://example.com>)", &restricted), vec![
+            Bold(vec![MaskedLink { content: vec![Text("docs")], href: "https://docs.rs", suppress_embed: false }]),
+            Text(" "),
+            MaskedLink { content: vec![Text("foo")], href: "http://example.com", suppress_embed: true },
+        ]);
+        // The alt text is parsed as markdown, without links
+        assert_eq!(parse_with_options("[**a** <b>](https://a.com)", &restricted), vec![
+            MaskedLink {
+                content: vec![Bold(vec![Text("a")]), Text(" <b>")],
+                href: "https://a.com",
+                suppress_embed: false,
+            },
+        ]);
+        // Alt text that looks like a URL could be used to spoof the link
+        assert_eq!(parse_with_options("[https://good.com](https://evil.com)", &restricted), vec![
+            Text("["),
+            Hyperlink { text: "https://good.com", href: "https://good.com", suppress_embed: false },
+            Text("]("),
+            Hyperlink { text: "https://evil.com", href: "https://evil.com", suppress_embed: false },
+            Text(")"),
+        ]);
+        assert_eq!(parse_with_options("[good.com/login](https://evil.com)", &restricted), vec![
+            Text("[good.com/login]("),
+            Hyperlink { text: "https://evil.com", href: "https://evil.com", suppress_embed: false },
+            Text(")"),
+        ]);
+        // Formatting and escapes in the alt text aren't shown, so they can't hide a URL
+        assert_eq!(parse_with_options("[**google.com**](https://evil.com)", &restricted), vec![
+            Text("["),
+            Bold(vec![Text("google.com")]),
+            Text("]("),
+            Hyperlink { text: "https://evil.com", href: "https://evil.com", suppress_embed: false },
+            Text(")"),
+        ]);
+        assert_eq!(parse_with_options("[\\google.com](https://evil.com)", &restricted), vec![
+            Text("["),
+            Text("g"),
+            Text("oogle.com]("),
+            Hyperlink { text: "https://evil.com", href: "https://evil.com", suppress_embed: false },
+            Text(")"),
+        ]);
+        // A domain anywhere in the alt text could be used to spoof the link too
+        for input in ["[google.com login](https://evil.com)", "[see paypal.com](https://evil.com)", "[google。com](https://evil.com)"] {
+            assert!(
+                !parse_with_options(input, &restricted).iter().any(|e| matches!(e, MaskedLink { .. })),
+                "{}",
+                input,
+            );
+        }
+        // Emoji and mentions are shown, so they're enough for the alt text
+        assert_eq!(parse_with_options("[🎉](https://a.com) [<@1>](https://b.com)", &restricted), vec![
+            MaskedLink { content: vec![Emoji("🎉")], href: "https://a.com", suppress_embed: false },
+            Text(" "),
+            MaskedLink { content: vec![User("1")], href: "https://b.com", suppress_embed: false },
+        ]);
+        // Nested links and links that aren't http(s) aren't allowed
+        assert_eq!(parse_with_options("[[a](https://a.com)](https://b.com)", &restricted), vec![
+            Text("["),
+            MaskedLink { content: vec![Text("a")], href: "https://a.com", suppress_embed: false },
+            Text("]("),
+            Hyperlink { text: "https://b.com", href: "https://b.com", suppress_embed: false },
+            Text(")"),
+        ]);
+        assert_eq!(parse_with_options("[ ](https://a.com)[a](ftp://a.com)", &restricted), vec![
+            Text("[ ]("),
+            Hyperlink { text: "https://a.com", href: "https://a.com", suppress_embed: false },
+            Text(")[a]("),
+            Hyperlink { text: "ftp://a.com", href: "ftp://a.com", suppress_embed: false },
+            Text(")"),
+        ]);
+        let unrestricted = ParseOptions { masked_links: MaskedLinks::Unrestricted, ..ParseOptions::default() };
+        assert_eq!(parse_with_options("[https://good.com](https://evil.com)", &unrestricted), vec![
+            MaskedLink { content: vec![Text("https://good.com")], href: "https://evil.com", suppress_embed: false },
+        ]);
+    }
+
+    #[test]
+    fn parse_timestamps() {
+        assert_eq!(parse("<t:1618953630:R><t:-1618953630>"), vec![
+            Timestamp { unix: 1618953630, style: TimestampStyle::Relative },
+            Timestamp { unix: -1618953630, style: TimestampStyle::ShortDateTime },
+        ]);
+        assert_eq!(parse("<t:1618953630:x>"), vec![Text("<t:1618953630:x>")]);
+        assert_eq!(parse("<t:99999999999999999999>"), vec![Text("<t:99999999999999999999>")]);
+    }
+
+    #[test]
+    fn parse_spans() {
+        // Escaped characters include the backslash, and mentions have the ID as their content
+        assert_eq!(parse_spanned("\\*a <@123>").1, vec![
+            span(0..2, 1..2, vec![]), span(2..4, 2..4, vec![]), span(4..10, 6..9, vec![])
+        ]);
+        // Offsets are in bytes
+        assert_eq!(parse_spanned("é\\é `c`").1, vec![
+            span(0..2, 0..2, vec![]),
+            span(2..5, 3..5, vec![]),
+            span(5..6, 5..6, vec![]),
+            span(6..9, 7..8, vec![]),
+        ]);
+        assert_eq!(parse_spanned("> __x__\nc").1, vec![
+            span(0..8, 2..7, vec![span(2..7, 4..5, vec![span(4..5, 4..5, vec![])])]),
+            span(8..9, 8..9, vec![]),
+        ]);
+        // List items contain their nested lists
+        assert_eq!(parse_spanned("- a\n  - b\n- c").1, vec![
+            span(0..13, 0..13, vec![
+                span(0..10, 2..3, vec![
+                    span(2..3, 2..3, vec![]),
+                    span(4..10, 4..10, vec![span(4..9, 8..9, vec![span(8..9, 8..9, vec![])])]),
+                ]),
+                span(10..13, 12..13, vec![span(12..13, 12..13, vec![])]),
+            ])
+        ]);
+    }
+
+    #[test]
+    fn parse_into_owned() {
+        let input = String::from("**hi <@123>** <id:linked-roles:456>\n- [a](<https://a.com>)\n```rs\nfoo```");
+        let ast = parse_with_options(&input, &ParseOptions {
+            masked_links: MaskedLinks::Unrestricted,
+            ..ParseOptions::default()
+        });
+        let owned: Vec<OwnedExpression> = ast.into_iter().map(Expression::into_owned).collect();
+        drop(input);
+        // The owned AST can be moved to another thread after the input is dropped
+        let owned = std::thread::spawn(move || owned).join().unwrap();
+        assert_eq!(owned[0], OwnedExpression::Bold(vec![
+            OwnedExpression::Text("hi ".to_string()), OwnedExpression::User("123".to_string())
+        ]));
+        assert_eq!(format!("{:?}", owned[0]), r#"Bold([Text("hi "), User("123")])"#);
+        let ast: Vec<Expression> = owned.iter().map(OwnedExpression::as_expression).collect();
+        assert_eq!(ast, vec![
+            Bold(vec![Text("hi "), User("123")]),
+            Text(" "),
+            GuildNavigation(GuildNavigationKind::LinkedRoles(Some("456"))),
+            Newline,
+            List { ordered: false, start: 1, items: vec![vec![
+                MaskedLink { content: vec![Text("a")], href: "https://a.com", suppress_embed: true },
+            ]] },
+            MultilineCode { language: Some("rs"), code: "foo" },
+        ]);
+        // Owned and borrowed expressions can be compared with each other
+        assert_eq!(owned[5], ast[5]);
+        assert_eq!(ast[0], owned[0]);
+        assert_ne!(ast[1], owned[0]);
+    }
+
+    #[test]
+    fn parse_references() {
+        let ast = parse_with_options(
+            "<@1> **<@2> <@1>** <@&3> <#4>\n> <a:foo:5> <:bar:6> <a:foo:5>\n- https://a.com https://discord.com/channels/7/8/9\n\
+            ```\n<@10> https://b.com\n``` `<#11>` [c](https://a.com) ||<@&3> <@12>|| **@here** `@everyone`",
+            &ParseOptions { masked_links: MaskedLinks::Unrestricted, ..ParseOptions::default() },
+        );
+        assert_eq!(references(&ast), References {
+            users: vec!["1", "2", "12"],
+            roles: vec!["3"],
+            channels: vec!["4", "8"],
+            emoji: vec![
+                CustomEmojiReference { name: "foo", id: "5", animated: true },
+                CustomEmojiReference { name: "bar", id: "6", animated: false },
+            ],
+            urls: vec!["https://a.com"],
+            message_links: vec![MessageLinkReference { guild: "7", channel: "8", message: Some("9") }],
+            everyone: false,
+            here: true,
+        });
+        assert_eq!(references(&parse("```<@1>```")), References::default());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serialize_ast() {
+        let ast = parse("# **hi** <@123>\n<t:1618953630:R> <id:linked-roles>");
+        let json = serde_json::to_string(&ast).unwrap();
+        assert_eq!(json, concat!(
+            r#"[{"type":"heading","content":[1,[{"type":"bold","content":[{"type":"text","content":"hi"}]},"#,
+            r#"{"type":"text","content":" "},{"type":"user","content":"123"}]]},"#,
+            r#"{"type":"timestamp","content":{"unix":1618953630,"style":"relative"}},{"type":"text","content":" "},"#,
+            r#"{"type":"guild_navigation","content":{"type":"linked_roles","content":null}}]"#,
+        ));
+        // Owned expressions have the same representation and can be deserialized
+        let owned: Vec<OwnedExpression> = serde_json::from_str(&json).unwrap();
+        assert_eq!(owned, ast);
+        assert_eq!(serde_json::to_string(&owned).unwrap(), json);
+    }
 }
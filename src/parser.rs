@@ -1,20 +1,95 @@
 //! Parse Discord MarkDown into an AST
 
-use nom::{IResult, Slice, branch::alt, bytes::complete::{is_not, tag, take_until}, combinator::{cond, map_opt, map_parser, recognize}, regex::Regex, sequence::{delimited, pair, preceded, terminated}};
+use nom::{IResult, Slice, branch::alt, bytes::complete::{is_not, tag, take_until, take_while}, character::complete::digit1, combinator::{cond, map, map_opt, map_parser, map_res, recognize, rest, verify}, regex::Regex, sequence::{delimited, pair, preceded, terminated, tuple}};
+use std::ops::Range;
 use lazy_static::lazy_static;
+use unicode_segmentation::UnicodeSegmentation;
 
 /// Enum to represent the AST
+///
+/// ## Serialization
+/// With the `serde` feature, expressions are serialized as objects with the name of the variant in
+/// snake_case as `type`, and the fields of the variant as `content` if it has any. Variants with a
+/// single field have that field as their content, other tuple variants have an array, and struct
+/// variants have an object with the same field names:
+///
+/// ```json
+/// {"type": "bold", "content": [{"type": "text", "content": "hi "}, {"type": "user", "content": "123"}]}
+/// {"type": "heading", "content": [1, [{"type": "text", "content": "title"}]]}
+/// {"type": "timestamp", "content": {"unix": 1618953630, "style": "relative"}}
+/// {"type": "newline"}
+/// ```
+///
+/// `GuildNavigationKind` uses the same representation, and `TimestampStyle` is serialized as the
+/// name of the style in snake_case. Only `OwnedExpression` can be deserialized, and it uses the
+/// same representation as `Expression`. The representation won't change without a major version
+/// bump.
 #[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", content = "content", rename_all = "snake_case"))]
 pub enum Expression<'a> {
     Text(&'a str),
     CustomEmoji(&'a str, String),
+    Emoji(&'a str),
     User(&'a str),
     Role(&'a str),
+    Everyone,
+    Here,
     Channel(&'a str),
-    Hyperlink(&'a str, &'a str),
-    MultilineCode(&'a str),
+    /// A link to one of the special pages of a server, like `<id:customize>`
+    GuildNavigation(GuildNavigationKind<'a>),
+    /// A mention of a slash command, like `</settings user color:123456789123456789>`
+    SlashCommand {
+        name: &'a str,
+        subcommand_group: Option<&'a str>,
+        subcommand: Option<&'a str>,
+        id: &'a str,
+    },
+    /// A timestamp, given as the number of seconds since the Unix epoch
+    Timestamp {
+        unix: i64,
+        style: TimestampStyle,
+    },
+    /// A link to a channel or message, like `https://discord.com/channels/1234/5678/9012`. `guild`
+    /// is `@me` for direct messages, and `suppress_embed` is the same as for `Hyperlink`.
+    MessageLink {
+        guild: &'a str,
+        channel: &'a str,
+        message: Option<&'a str>,
+        suppress_embed: bool,
+    },
+    /// A link, where `suppress_embed` is true if the URL was surrounded by angle brackets (like
+    /// `<https://example.com>`) to stop discord from showing an embed for it
+    Hyperlink {
+        text: &'a str,
+        href: &'a str,
+        suppress_embed: bool,
+    },
+    /// A link with alt text, like `[**example**](https://example.com)`. The alt text is parsed
+    /// like the rest of the message, except that it can't contain other links.
+    MaskedLink {
+        content: Vec<Expression<'a>>,
+        href: &'a str,
+        suppress_embed: bool,
+    },
+    /// A fenced code block. `language` is the first line of the block if it's a valid language
+    /// tag, and `code` doesn't include the language tag or surrounding newlines.
+    MultilineCode {
+        language: Option<&'a str>,
+        code: &'a str,
+    },
     InlineCode(&'a str),
     Blockquote(Vec<Expression<'a>>),
+    Heading(u8, Vec<Expression<'a>>),
+    Subtext(Vec<Expression<'a>>),
+    /// A list where each item is a vector of `Expression`s. Nested lists are stored as an
+    /// `Expression::List` at the end of the item they belong to. `start` is the number of the
+    /// first item, and is always 1 for unordered lists.
+    List {
+        ordered: bool,
+        start: u64,
+        items: Vec<Vec<Expression<'a>>>,
+    },
     Spoiler(Vec<Expression<'a>>),
     Underline(Vec<Expression<'a>>),
     Strikethrough(Vec<Expression<'a>>),
@@ -23,12 +98,453 @@ pub enum Expression<'a> {
     Newline,
 }
 
+/// An owned version of `Expression` that doesn't borrow from the input string, so it can be
+/// stored or sent to another thread after the input is dropped. Use `Expression::into_owned` to
+/// create one, and `OwnedExpression::as_expression` to pass it to the convertor.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", content = "content", rename_all = "snake_case"))]
+pub enum OwnedExpression {
+    Text(String),
+    CustomEmoji(String, String),
+    Emoji(String),
+    User(String),
+    Role(String),
+    Everyone,
+    Here,
+    Channel(String),
+    GuildNavigation(OwnedGuildNavigationKind),
+    SlashCommand {
+        name: String,
+        subcommand_group: Option<String>,
+        subcommand: Option<String>,
+        id: String,
+    },
+    Timestamp {
+        unix: i64,
+        style: TimestampStyle,
+    },
+    MessageLink {
+        guild: String,
+        channel: String,
+        message: Option<String>,
+        suppress_embed: bool,
+    },
+    Hyperlink {
+        text: String,
+        href: String,
+        suppress_embed: bool,
+    },
+    MaskedLink {
+        content: Vec<OwnedExpression>,
+        href: String,
+        suppress_embed: bool,
+    },
+    MultilineCode {
+        language: Option<String>,
+        code: String,
+    },
+    InlineCode(String),
+    Blockquote(Vec<OwnedExpression>),
+    Heading(u8, Vec<OwnedExpression>),
+    Subtext(Vec<OwnedExpression>),
+    List {
+        ordered: bool,
+        start: u64,
+        items: Vec<Vec<OwnedExpression>>,
+    },
+    Spoiler(Vec<OwnedExpression>),
+    Underline(Vec<OwnedExpression>),
+    Strikethrough(Vec<OwnedExpression>),
+    Bold(Vec<OwnedExpression>),
+    Italics(Vec<OwnedExpression>),
+    Newline,
+}
+
+fn into_owned_vec(expressions: Vec<Expression>) -> Vec<OwnedExpression> {
+    expressions.into_iter().map(Expression::into_owned).collect()
+}
+
+fn as_expression_vec(expressions: &[OwnedExpression]) -> Vec<Expression<'_>> {
+    expressions.iter().map(OwnedExpression::as_expression).collect()
+}
+
+impl Expression<'_> {
+    /// Copies everything this expression borrows from the input into an `OwnedExpression`
+    pub fn into_owned(self) -> OwnedExpression {
+        match self {
+            Expression::Text(text) => OwnedExpression::Text(text.to_string()),
+            Expression::CustomEmoji(name, url) => OwnedExpression::CustomEmoji(name.to_string(), url),
+            Expression::Emoji(emoji) => OwnedExpression::Emoji(emoji.to_string()),
+            Expression::User(id) => OwnedExpression::User(id.to_string()),
+            Expression::Role(id) => OwnedExpression::Role(id.to_string()),
+            Expression::Everyone => OwnedExpression::Everyone,
+            Expression::Here => OwnedExpression::Here,
+            Expression::Channel(id) => OwnedExpression::Channel(id.to_string()),
+            Expression::GuildNavigation(kind) => OwnedExpression::GuildNavigation(kind.into_owned()),
+            Expression::SlashCommand { name, subcommand_group, subcommand, id } => OwnedExpression::SlashCommand {
+                name: name.to_string(),
+                subcommand_group: subcommand_group.map(str::to_string),
+                subcommand: subcommand.map(str::to_string),
+                id: id.to_string(),
+            },
+            Expression::Timestamp { unix, style } => OwnedExpression::Timestamp { unix, style },
+            Expression::MessageLink { guild, channel, message, suppress_embed } => OwnedExpression::MessageLink {
+                guild: guild.to_string(),
+                channel: channel.to_string(),
+                message: message.map(str::to_string),
+                suppress_embed,
+            },
+            Expression::Hyperlink { text, href, suppress_embed } => OwnedExpression::Hyperlink {
+                text: text.to_string(),
+                href: href.to_string(),
+                suppress_embed,
+            },
+            Expression::MaskedLink { content, href, suppress_embed } => OwnedExpression::MaskedLink {
+                content: into_owned_vec(content),
+                href: href.to_string(),
+                suppress_embed,
+            },
+            Expression::MultilineCode { language, code } => OwnedExpression::MultilineCode {
+                language: language.map(str::to_string),
+                code: code.to_string(),
+            },
+            Expression::InlineCode(code) => OwnedExpression::InlineCode(code.to_string()),
+            Expression::Blockquote(e) => OwnedExpression::Blockquote(into_owned_vec(e)),
+            Expression::Heading(level, e) => OwnedExpression::Heading(level, into_owned_vec(e)),
+            Expression::Subtext(e) => OwnedExpression::Subtext(into_owned_vec(e)),
+            Expression::List { ordered, start, items } => OwnedExpression::List {
+                ordered,
+                start,
+                items: items.into_iter().map(into_owned_vec).collect(),
+            },
+            Expression::Spoiler(e) => OwnedExpression::Spoiler(into_owned_vec(e)),
+            Expression::Underline(e) => OwnedExpression::Underline(into_owned_vec(e)),
+            Expression::Strikethrough(e) => OwnedExpression::Strikethrough(into_owned_vec(e)),
+            Expression::Bold(e) => OwnedExpression::Bold(into_owned_vec(e)),
+            Expression::Italics(e) => OwnedExpression::Italics(into_owned_vec(e)),
+            Expression::Newline => OwnedExpression::Newline,
+        }
+    }
+}
+
+impl OwnedExpression {
+    /// Returns an `Expression` that borrows from this one
+    pub fn as_expression(&self) -> Expression<'_> {
+        match self {
+            OwnedExpression::Text(text) => Expression::Text(text),
+            OwnedExpression::CustomEmoji(name, url) => Expression::CustomEmoji(name, url.clone()),
+            OwnedExpression::Emoji(emoji) => Expression::Emoji(emoji),
+            OwnedExpression::User(id) => Expression::User(id),
+            OwnedExpression::Role(id) => Expression::Role(id),
+            OwnedExpression::Everyone => Expression::Everyone,
+            OwnedExpression::Here => Expression::Here,
+            OwnedExpression::Channel(id) => Expression::Channel(id),
+            OwnedExpression::GuildNavigation(kind) => Expression::GuildNavigation(kind.as_kind()),
+            OwnedExpression::SlashCommand { name, subcommand_group, subcommand, id } => Expression::SlashCommand {
+                name,
+                subcommand_group: subcommand_group.as_deref(),
+                subcommand: subcommand.as_deref(),
+                id,
+            },
+            OwnedExpression::Timestamp { unix, style } => Expression::Timestamp { unix: *unix, style: *style },
+            OwnedExpression::MessageLink { guild, channel, message, suppress_embed } => Expression::MessageLink {
+                guild,
+                channel,
+                message: message.as_deref(),
+                suppress_embed: *suppress_embed,
+            },
+            OwnedExpression::Hyperlink { text, href, suppress_embed } => Expression::Hyperlink {
+                text,
+                href,
+                suppress_embed: *suppress_embed,
+            },
+            OwnedExpression::MaskedLink { content, href, suppress_embed } => Expression::MaskedLink {
+                content: as_expression_vec(content),
+                href,
+                suppress_embed: *suppress_embed,
+            },
+            OwnedExpression::MultilineCode { language, code } => Expression::MultilineCode {
+                language: language.as_deref(),
+                code,
+            },
+            OwnedExpression::InlineCode(code) => Expression::InlineCode(code),
+            OwnedExpression::Blockquote(e) => Expression::Blockquote(as_expression_vec(e)),
+            OwnedExpression::Heading(level, e) => Expression::Heading(*level, as_expression_vec(e)),
+            OwnedExpression::Subtext(e) => Expression::Subtext(as_expression_vec(e)),
+            OwnedExpression::List { ordered, start, items } => Expression::List {
+                ordered: *ordered,
+                start: *start,
+                items: items.iter().map(|item| as_expression_vec(item)).collect(),
+            },
+            OwnedExpression::Spoiler(e) => Expression::Spoiler(as_expression_vec(e)),
+            OwnedExpression::Underline(e) => Expression::Underline(as_expression_vec(e)),
+            OwnedExpression::Strikethrough(e) => Expression::Strikethrough(as_expression_vec(e)),
+            OwnedExpression::Bold(e) => Expression::Bold(as_expression_vec(e)),
+            OwnedExpression::Italics(e) => Expression::Italics(as_expression_vec(e)),
+            OwnedExpression::Newline => Expression::Newline,
+        }
+    }
+}
+
+impl PartialEq<OwnedExpression> for Expression<'_> {
+    fn eq(&self, other: &OwnedExpression) -> bool {
+        *self == other.as_expression()
+    }
+}
+
+impl PartialEq<Expression<'_>> for OwnedExpression {
+    fn eq(&self, other: &Expression) -> bool {
+        self.as_expression() == *other
+    }
+}
+
+/// The position of an `Expression` in the input string, as byte offsets
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Span {
+    /// The whole expression, including any delimiters
+    pub range: Range<usize>,
+    /// The content of the expression inside its delimiters, like `foo` in `**foo**`, the code in a
+    /// code block, the alt text of a link, or the ID in a mention. This is the same as `range` for
+    /// expressions without any content.
+    pub content: Range<usize>,
+    /// The spans of the expressions nested in this expression, in the same order. For lists, there
+    /// is a span for each item, with the spans of the expressions in that item as its `children`.
+    pub children: Vec<Span>,
+}
+
+/// Options to enable parsing rules that are off by default
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseOptions {
+    /// The URL schemes that are turned into hyperlinks, compared case-insensitively. Defaults to
+    /// `DEFAULT_LINK_SCHEMES`.
+    pub link_schemes: Vec<String>,
+    /// How to parse hyperlinks with alt text, like `[example](https://example.com)`
+    pub masked_links: MaskedLinks,
+    /// Convert emoji shortcodes like `:thumbsup:` into `Expression::Emoji`. Unknown shortcodes are
+    /// left as text.
+    pub emoji_shortcodes: bool,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            link_schemes: DEFAULT_LINK_SCHEMES.iter().map(|scheme| scheme.to_string()).collect(),
+            masked_links: MaskedLinks::default(),
+            emoji_shortcodes: false,
+        }
+    }
+}
+
+/// The URL schemes that are turned into hyperlinks by default
+pub const DEFAULT_LINK_SCHEMES: &[&str] = &["http", "https", "ftp", "file", "steam", "mailto"];
+
+// The schemes that have to be followed by `://` instead of just a colon
+const HIERARCHICAL_SCHEMES: &[&str] = &["http", "https", "ftp", "file", "steam"];
+
+/// The rules used for parsing hyperlinks with alt text, like `[example](https://example.com)`
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum MaskedLinks {
+    /// Don't parse hyperlinks with alt text
+    #[default]
+    Disabled,
+    /// Parse hyperlinks with any alt text, like discord does in embeds
+    Unrestricted,
+    /// Parse hyperlinks with the restrictions discord has in messages, so the alt text can't be
+    /// used to make the link look like it goes somewhere else. The alt text can't look like a URL
+    /// or contain another link, and only `http` and `https` links are allowed.
+    Restricted,
+}
+
+/// The page of a server that an `Expression::GuildNavigation` links to
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", content = "content", rename_all = "snake_case"))]
+pub enum GuildNavigationKind<'a> {
+    /// `<id:customize>`, the Channels & Roles page
+    Customize,
+    /// `<id:browse>`, the Browse Channels page
+    Browse,
+    /// `<id:guide>`, the Server Guide
+    Guide,
+    /// `<id:home>`, the old name of the Server Guide
+    Home,
+    /// `<id:linked-roles>` or `<id:linked-roles:123456789123456789>`, the Linked Roles page,
+    /// optionally for a specific role
+    LinkedRoles(Option<&'a str>),
+}
+
+impl GuildNavigationKind<'_> {
+    /// Returns the name used for this kind in the markup, like `customize` for `<id:customize>`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Customize => "customize",
+            Self::Browse => "browse",
+            Self::Guide => "guide",
+            Self::Home => "home",
+            Self::LinkedRoles(_) => "linked-roles",
+        }
+    }
+
+    /// Copies the role ID this kind borrows from the input into an `OwnedGuildNavigationKind`
+    pub fn into_owned(self) -> OwnedGuildNavigationKind {
+        match self {
+            Self::Customize => OwnedGuildNavigationKind::Customize,
+            Self::Browse => OwnedGuildNavigationKind::Browse,
+            Self::Guide => OwnedGuildNavigationKind::Guide,
+            Self::Home => OwnedGuildNavigationKind::Home,
+            Self::LinkedRoles(id) => OwnedGuildNavigationKind::LinkedRoles(id.map(str::to_string)),
+        }
+    }
+}
+
+/// An owned version of `GuildNavigationKind`, used in `OwnedExpression`
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", content = "content", rename_all = "snake_case"))]
+pub enum OwnedGuildNavigationKind {
+    Customize,
+    Browse,
+    Guide,
+    Home,
+    LinkedRoles(Option<String>),
+}
+
+impl OwnedGuildNavigationKind {
+    /// Returns a `GuildNavigationKind` that borrows from this one
+    pub fn as_kind(&self) -> GuildNavigationKind<'_> {
+        match self {
+            Self::Customize => GuildNavigationKind::Customize,
+            Self::Browse => GuildNavigationKind::Browse,
+            Self::Guide => GuildNavigationKind::Guide,
+            Self::Home => GuildNavigationKind::Home,
+            Self::LinkedRoles(id) => GuildNavigationKind::LinkedRoles(id.as_deref()),
+        }
+    }
+}
+
+/// The style a timestamp is displayed in, specified by the letter after the Unix time in
+/// `<t:1618953630:R>`
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum TimestampStyle {
+    /// `t`, like 16:20
+    ShortTime,
+    /// `T`, like 16:20:30
+    LongTime,
+    /// `d`, like 20/04/2021
+    ShortDate,
+    /// `D`, like 20 April 2021
+    LongDate,
+    /// `f`, like 20 April 2021 16:20. This is the default if no style is specified.
+    #[default]
+    ShortDateTime,
+    /// `F`, like Tuesday, 20 April 2021 16:20
+    LongDateTime,
+    /// `R`, like 2 months ago
+    Relative,
+}
+
+impl TimestampStyle {
+    /// Returns the style corresponding to the given letter, if there is one
+    pub fn from_char(c: char) -> Option<Self> {
+        match c {
+            't' => Some(Self::ShortTime),
+            'T' => Some(Self::LongTime),
+            'd' => Some(Self::ShortDate),
+            'D' => Some(Self::LongDate),
+            'f' => Some(Self::ShortDateTime),
+            'F' => Some(Self::LongDateTime),
+            'R' => Some(Self::Relative),
+            _ => None,
+        }
+    }
+
+    /// Returns the letter used to specify this style
+    pub fn as_char(self) -> char {
+        match self {
+            Self::ShortTime => 't',
+            Self::LongTime => 'T',
+            Self::ShortDate => 'd',
+            Self::LongDate => 'D',
+            Self::ShortDateTime => 'f',
+            Self::LongDateTime => 'F',
+            Self::Relative => 'R',
+        }
+    }
+}
+
+/// The users, roles, channels, custom emoji, URLs and messages referenced in an AST, and whether
+/// it mentions `@everyone` or `@here`, returned by `references`. Each of them is only included
+/// once, in the order they first appear in.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct References<'a> {
+    /// The IDs of mentioned users
+    pub users: Vec<&'a str>,
+    /// The IDs of mentioned roles
+    pub roles: Vec<&'a str>,
+    /// The IDs of mentioned channels, including the channels of linked messages
+    pub channels: Vec<&'a str>,
+    /// The custom emoji used. An emoji used with different names is in here once for each name.
+    pub emoji: Vec<CustomEmojiReference<'a>>,
+    /// The URLs of hyperlinks, other than links to messages
+    pub urls: Vec<&'a str>,
+    /// Links to messages and channels on discord.com
+    pub message_links: Vec<MessageLinkReference<'a>>,
+    /// Whether `@everyone` is mentioned
+    pub everyone: bool,
+    /// Whether `@here` is mentioned
+    pub here: bool,
+}
+
+/// A custom emoji in a message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CustomEmojiReference<'a> {
+    /// The name the emoji is used with, which can be different for the same emoji
+    pub name: &'a str,
+    /// The ID of the emoji, without the file extension in `Expression::CustomEmoji`
+    pub id: &'a str,
+    /// Whether the emoji is used as an animated emoji, with `<a:`
+    pub animated: bool,
+}
+
+/// A link to a message or channel in a message, with the same IDs as `Expression::MessageLink`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MessageLinkReference<'a> {
+    /// The ID of the server, or `@me` for direct messages
+    pub guild: &'a str,
+    /// The ID of the channel
+    pub channel: &'a str,
+    /// The ID of the message, or `None` for links to channels
+    pub message: Option<&'a str>,
+}
+
+impl MessageLinkReference<'_> {
+    /// The URL of the link on discord.com, however it was written in the message
+    pub fn url(&self) -> String {
+        let message = self.message.map(|id| format!("/{}", id)).unwrap_or_default();
+        format!("https://discord.com/channels/{}/{}{}", self.guild, self.channel, message)
+    }
+}
+
+// Discord doesn't render timestamps that are out of the range that JavaScript dates support
+const MAX_TIMESTAMP: i64 = 8_640_000_000_000;
+
 lazy_static! {
     static ref CUSTOM_EMOJI_RE: Regex = Regex::new(r"^<(a?):(\w+):(\d+)(>)").unwrap();
+    static ref EMOJI_SHORTCODE_RE: Regex = Regex::new(r"^:([\w+-]+)(:)").unwrap();
     static ref USER_RE: Regex = Regex::new(r"^<@!?(\d+)(>)").unwrap();
     static ref ROLE_RE: Regex = Regex::new(r"^<@&(\d+)(>)").unwrap();
     static ref CHANNEL_RE: Regex = Regex::new(r"^<#(\d+)(>)").unwrap();
-    static ref LINK_RE: Regex = Regex::new(r"^(https?|ftp|file)(://[-A-Za-z0-9+&@#/%?=~_|!:,.;]*[A-Za-z0-9+&@#/%=~_|])").unwrap();
+    static ref GUILD_NAVIGATION_RE: Regex = Regex::new(r"^<id:(customize|browse|guide|home|linked-roles(?::(\d+))?)(>)").unwrap();
+    // Anything that looks like a domain, including with the dots other scripts use
+    static ref DOMAIN_RE: Regex = Regex::new(r"[\w-]+(?:[.。．｡][\w-]+)*[.。．｡]\p{L}{2,}").unwrap();
+    // Like other links, the scheme and host aren't case-sensitive
+    static ref MESSAGE_LINK_RE: Regex = Regex::new(r"^(?i:https://(?:(?:ptb|canary)\.)?discord(?:app)?\.com)/channels/(\d+|@me)/(\d+)(?:/(\d+))?/?$").unwrap();
+    static ref SLASH_COMMAND_RE: Regex = Regex::new(r"^</([-_\p{L}\p{N}]+(?: [-_\p{L}\p{N}]+){0,2}):(\d+)(>)").unwrap();
+    static ref TIMESTAMP_RE: Regex = Regex::new(r"^<t:(-?\d+)(:[tTdDfFR])?(>)").unwrap();
 }
 
 // Re-implement re_capture from nom, but make it take &'a Regex instead of Regex
@@ -41,8 +557,7 @@ fn re_capture<'a, E>(re: &'a Regex) -> impl Fn(&'a str) -> IResult<&'a str, Vec<
         if let Some(c) = re.captures(i) {
             let v: Vec<_> = c
                 .iter()
-                .filter(|el| el.is_some())
-                .map(|el| el.unwrap())
+                .flatten()
                 .map(|m| i.slice(m.start()..m.end()))
                 .collect();
             let offset = {
@@ -57,65 +572,343 @@ fn re_capture<'a, E>(re: &'a Regex) -> impl Fn(&'a str) -> IResult<&'a str, Vec<
 }
 
 // Parses custom emoji
-fn custom_emoji<'a>(input: &'a str) -> IResult<&str, Expression<'a>> {
+fn custom_emoji<'a>(input: &'a str) -> IResult<&'a str, Expression<'a>> {
     let (input, custom_emoji) = re_capture(&CUSTOM_EMOJI_RE)(input)?;
     let extension = if custom_emoji[1] == "a" { "gif" } else { "png" };
     Ok((input, Expression::CustomEmoji(custom_emoji[2], format!("{}.{}", custom_emoji[3], extension))))
 }
 
+// Takes the first extended grapheme cluster of the input
+fn grapheme(input: &str) -> IResult<&str, &str> {
+    match input.graphemes(true).next() {
+        Some(grapheme) => Ok((&input[grapheme.len()..], grapheme)),
+        None => Err(nom::Err::Error(nom::error::make_error(input, nom::error::ErrorKind::Eof))),
+    }
+}
+
+// Checks if a grapheme cluster is a unicode emoji the way Discord displays it
+fn is_emoji(grapheme: &str) -> bool {
+    // Lone ASCII characters like digits aren't emoji unless they're part of a keycap sequence, and
+    // Discord shows these symbols as text unless they're followed by a variation selector
+    if grapheme.len() == 1 || matches!(grapheme, "©" | "®" | "™") {
+        return false;
+    }
+    emojis::get(grapheme).is_some()
+}
+
+// Parses unicode emoji, including ZWJ sequences, skin tones, and flags
+fn emoji<'a>(input: &'a str) -> IResult<&'a str, Expression<'a>> {
+    let (input, emoji) = verify(grapheme, is_emoji)(input)?;
+    Ok((input, Expression::Emoji(emoji)))
+}
+
+// Discord's names for emoji that gemoji, which the `emojis` crate uses, names differently, sorted by
+// Discord's name
+const DISCORD_SHORTCODES: &[(&str, &str)] = &[
+    ("cloud_rain", "cloud_with_rain"),
+    ("clown", "clown_face"),
+    ("cowboy", "cowboy_hat_face"),
+    ("face_palm", "facepalm"),
+    ("face_vomiting", "vomiting_face"),
+    ("face_with_hand_over_mouth", "hand_over_mouth"),
+    ("face_with_monocle", "monocle_face"),
+    ("face_with_symbols_over_mouth", "cursing_face"),
+    ("flag_black", "black_flag"),
+    ("flag_uk", "gb"),
+    ("flag_white", "white_flag"),
+    ("frowning2", "frowning_face"),
+    ("hand_splayed", "raised_hand_with_fingers_splayed"),
+    ("head_bandage", "face_with_head_bandage"),
+    ("heart_exclamation", "heavy_heart_exclamation"),
+    ("hugging", "hugs"),
+    ("money_mouth", "money_mouth_face"),
+    ("nerd", "nerd_face"),
+    ("rolling_eyes", "roll_eyes"),
+    ("slight_frown", "slightly_frowning_face"),
+    ("slight_smile", "slightly_smiling_face"),
+    ("smiling_face_with_3_hearts", "smiling_face_with_three_hearts"),
+    ("speech_left", "left_speech_bubble"),
+    ("spy", "detective"),
+    ("thermometer_face", "face_with_thermometer"),
+    ("thunder_cloud_rain", "cloud_with_lightning_and_rain"),
+    ("upside_down", "upside_down_face"),
+    ("white_sun_small_cloud", "sun_behind_small_cloud"),
+    ("zipper_mouth", "zipper_mouth_face"),
+];
+
+// The skin tone modifiers for `:skin-tone-1:` to `:skin-tone-5:`
+const SKIN_TONES: [&str; 5] = ["\u{1F3FB}", "\u{1F3FC}", "\u{1F3FD}", "\u{1F3FE}", "\u{1F3FF}"];
+
+// The regional indicator symbols for `:regional_indicator_a:` to `:regional_indicator_z:`
+const REGIONAL_INDICATORS: [&str; 26] = [
+    "🇦", "🇧", "🇨", "🇩", "🇪", "🇫", "🇬", "🇭", "🇮", "🇯", "🇰", "🇱", "🇲", "🇳", "🇴", "🇵", "🇶", "🇷", "🇸", "🇹", "🇺", "🇻", "🇼", "🇽", "🇾", "🇿"
+];
+
+// Looks up an emoji by its shortcode, using Discord's name for it if gemoji's is different
+fn get_by_shortcode(shortcode: &str) -> Option<&'static emojis::Emoji> {
+    match DISCORD_SHORTCODES.binary_search_by_key(&shortcode, |(discord, _)| discord) {
+        Ok(i) => emojis::get_by_shortcode(DISCORD_SHORTCODES[i].1),
+        Err(_) => emojis::get_by_shortcode(shortcode),
+    }
+}
+
+// Looks up the unicode emoji for a shortcode, including Discord's `_tone1` to `_tone5` skin tone
+// suffixes, `skin-tone-1` to `skin-tone-5` modifiers, regional indicators and `flag_xx` country flags
+fn shortcode_to_emoji(shortcode: &str) -> Option<&'static str> {
+    if let Some(emoji) = get_by_shortcode(shortcode) {
+        return Some(emoji.as_str());
+    }
+    if let Some(tone) = shortcode.strip_prefix("skin-tone-") {
+        return tone.parse::<usize>().ok().and_then(|tone| SKIN_TONES.get(tone.checked_sub(1)?)).copied();
+    }
+    if let Some(letter) = shortcode.strip_prefix("regional_indicator_") {
+        return match letter.as_bytes() {
+            [c @ b'a'..=b'z'] => Some(REGIONAL_INDICATORS[(c - b'a') as usize]),
+            _ => None,
+        };
+    }
+    if let Some((name, tone)) = shortcode.rsplit_once("_tone") {
+        let skin_tone = match tone {
+            "1" => emojis::SkinTone::Light,
+            "2" => emojis::SkinTone::MediumLight,
+            "3" => emojis::SkinTone::Medium,
+            "4" => emojis::SkinTone::MediumDark,
+            "5" => emojis::SkinTone::Dark,
+            _ => return None,
+        };
+        return get_by_shortcode(name)?.with_skin_tone(skin_tone).map(emojis::Emoji::as_str);
+    }
+    let country = shortcode.strip_prefix("flag_").filter(|country| {
+        country.len() == 2 && country.bytes().all(|c| c.is_ascii_lowercase())
+    })?;
+    // Flags are made of the regional indicator symbols corresponding to the country code
+    let flag: String = country.bytes().filter_map(|c| char::from_u32(0x1F1E6 + (c - b'a') as u32)).collect();
+    emojis::get(&flag).map(emojis::Emoji::as_str)
+}
+
+// Parses emoji shortcodes, like `:thumbsup:`
+fn emoji_shortcode<'a>(input: &'a str) -> IResult<&'a str, Expression<'a>> {
+    let (input, emoji) = map_opt(re_capture(&EMOJI_SHORTCODE_RE), |shortcode| {
+        shortcode_to_emoji(shortcode[1])
+    })(input)?;
+    Ok((input, Expression::Emoji(emoji)))
+}
+
 // Parses user mentions
-fn user<'a>(input: &'a str) -> IResult<&str, Expression<'a>> {
+fn user<'a>(input: &'a str) -> IResult<&'a str, Expression<'a>> {
     let (input, user) = re_capture(&USER_RE)(input)?;
     Ok((input, Expression::User(user[1])))
 }
 
 // Parses role mentions
-fn role<'a>(input: &'a str) -> IResult<&str, Expression<'a>> {
+fn role<'a>(input: &'a str) -> IResult<&'a str, Expression<'a>> {
     let (input, role) = re_capture(&ROLE_RE)(input)?;
     Ok((input, Expression::Role(role[1])))
 }
 
+// Parses @everyone mentions
+fn everyone<'a>(input: &'a str) -> IResult<&'a str, Expression<'a>> {
+    map(tag("@everyone"), |_| Expression::Everyone)(input)
+}
+
+// Parses @here mentions
+fn here<'a>(input: &'a str) -> IResult<&'a str, Expression<'a>> {
+    map(tag("@here"), |_| Expression::Here)(input)
+}
+
 // Parses channel links
-fn channel<'a>(input: &'a str) -> IResult<&str, Expression<'a>> {
+fn channel<'a>(input: &'a str) -> IResult<&'a str, Expression<'a>> {
     let (input, channel) = re_capture(&CHANNEL_RE)(input)?;
     Ok((input, Expression::Channel(channel[1])))
 }
 
-fn hyperlink_internals(input: &str) -> IResult<&str, (&str, &str)> {
-    let (input, hyperlink) = alt((
-        re_capture(&LINK_RE),
-        delimited(tag("<"), re_capture(&LINK_RE), tag(">")),
-    ))(input)?;
-    Ok((input, (hyperlink[0], hyperlink[0])))
+// Parses links to the special pages of a server
+fn guild_navigation<'a>(input: &'a str) -> IResult<&'a str, Expression<'a>> {
+    let (input, guild_navigation) = re_capture(&GUILD_NAVIGATION_RE)(input)?;
+    let kind = match guild_navigation[1] {
+        "customize" => GuildNavigationKind::Customize,
+        "browse" => GuildNavigationKind::Browse,
+        "guide" => GuildNavigationKind::Guide,
+        "home" => GuildNavigationKind::Home,
+        // The role ID is only captured if it's specified
+        _ => GuildNavigationKind::LinkedRoles(match guild_navigation.len() {
+            4 => Some(guild_navigation[2]),
+            _ => None,
+        }),
+    };
+    Ok((input, Expression::GuildNavigation(kind)))
 }
 
-// Parses hyperlinks
-fn hyperlink<'a>(input: &'a str) -> IResult<&str, Expression<'a>> {
-    let (input, hyperlink) = hyperlink_internals(input)?;
-    Ok((input, Expression::Hyperlink(hyperlink.0, hyperlink.1)))
-}
-
-// Parses hyperlinks with support for alt text
-fn md_hyperlink<'a>(input: &'a str) -> IResult<&str, Expression<'a>> {
-    let (input, hyperlink) = alt((
-        hyperlink_internals,
-        pair(
-            delimited(tag("["), take_until("]"), tag("]")),
-            delimited(tag("("), |input| {
-                let x = hyperlink_internals(input)?;
-                Ok((x.0, x.1.0))
-            }, tag(")"))
+// Parses slash command mentions
+fn slash_command<'a>(input: &'a str) -> IResult<&'a str, Expression<'a>> {
+    let (input, slash_command) = verify(
+        re_capture(&SLASH_COMMAND_RE),
+        // Command names can't be longer than 32 characters
+        |slash_command: &Vec<&str>| slash_command[1].split(' ').all(|word| word.chars().count() <= 32),
+    )(input)?;
+    let mut words = slash_command[1].split(' ');
+    let name = words.next().unwrap();
+    // If there are three words, the second one is the subcommand group
+    let (subcommand_group, subcommand) = match (words.next(), words.next()) {
+        (Some(group), Some(subcommand)) => (Some(group), Some(subcommand)),
+        (subcommand, _) => (None, subcommand),
+    };
+    Ok((input, Expression::SlashCommand { name, subcommand_group, subcommand, id: slash_command[2] }))
+}
+
+// Parses timestamps
+fn timestamp<'a>(input: &'a str) -> IResult<&'a str, Expression<'a>> {
+    let (input, (unix, style)) = map_opt(re_capture(&TIMESTAMP_RE), |timestamp| {
+        let unix = timestamp[1].parse::<i64>().ok().filter(|unix| unix.abs() <= MAX_TIMESTAMP)?;
+        // The style is only captured if it's specified
+        let style = match timestamp.len() {
+            4 => TimestampStyle::from_char(timestamp[2].chars().nth(1)?)?,
+            _ => TimestampStyle::default(),
+        };
+        Some((unix, style))
+    })(input)?;
+    Ok((input, Expression::Timestamp { unix, style }))
+}
+
+// Scans for a URL with one of the allowed schemes at the start of the input
+//
+// Unless the URL is bracketed (like `<https://example.com.>`, where the brackets show where the URL
+// ends), it stops at closing brackets that aren't balanced within the URL so links in parentheses
+// work while Wikipedia links don't get cut off, and punctuation at the end is left out.
+fn url<'a>(input: &'a str, schemes: &[String], bracketed: bool) -> IResult<&'a str, &'a str> {
+    let error = || nom::Err::Error(nom::error::make_error(input, nom::error::ErrorKind::Verify));
+    // The scheme has to be followed by a colon, or by `://` if the scheme needs it
+    let separator = |scheme: &str| {
+        if HIERARCHICAL_SCHEMES.iter().any(|s| s.eq_ignore_ascii_case(scheme)) { "://" } else { ":" }
+    };
+    let scheme = schemes.iter().find(|scheme| {
+        input.get(..scheme.len()).is_some_and(|s| s.eq_ignore_ascii_case(scheme))
+            && input[scheme.len()..].starts_with(separator(scheme))
+    }).ok_or_else(error)?;
+    let body_start = scheme.len() + separator(scheme).len();
+    let (mut parentheses, mut square_brackets) = (0, 0);
+    let body_len = input[body_start..].find(|c: char| match c {
+        '(' if !bracketed => { parentheses += 1; false }
+        ')' if !bracketed && parentheses == 0 => true,
+        ')' if !bracketed => { parentheses -= 1; false }
+        '[' if !bracketed => { square_brackets += 1; false }
+        ']' if !bracketed && square_brackets == 0 => true,
+        ']' if !bracketed => { square_brackets -= 1; false }
+        c => c.is_whitespace() || c == '<' || c == '>',
+    }).unwrap_or(input.len() - body_start);
+    let mut url = &input[..body_start + body_len];
+    if !bracketed {
+        url = url.trim_end_matches(&['.', ',', ':', ';', '"', '\'', '!', '?'][..]);
+    }
+    // There has to be something after the scheme, other than the slashes
+    if url.len() <= body_start || url[body_start..].trim_start_matches('/').is_empty() {
+        return Err(error());
+    }
+    Ok((&input[url.len()..], url))
+}
+
+// Parses a URL, and whether it's surrounded by angle brackets to suppress embeds
+fn hyperlink_internals<'a>(input: &'a str, options: &ParseOptions) -> IResult<&'a str, (&'a str, bool)> {
+    alt((
+        map(|input| url(input, &options.link_schemes, false), |href| (href, false)),
+        map(
+            delimited(tag("<"), |input| url(input, &options.link_schemes, true), tag(">")),
+            |href| (href, true),
         ),
-    ))(input)?;
-    Ok((input, Expression::Hyperlink(hyperlink.0, hyperlink.1)))
+    ))(input)
 }
 
-fn multiline_code<'a>(input: &'a str) -> IResult<&str, Expression<'a>> {
+// Parses hyperlinks
+fn hyperlink<'a>(input: &'a str, options: &ParseOptions) -> IResult<&'a str, Expression<'a>> {
+    let (input, (href, suppress_embed)) = hyperlink_internals(input, options)?;
+    // Links to discord channels and messages are shown differently
+    if let Some(message_link) = MESSAGE_LINK_RE.captures(href) {
+        let group = |i| message_link.get(i).map(|m| &href[m.start()..m.end()]);
+        return Ok((input, Expression::MessageLink {
+            guild: group(1).unwrap(),
+            channel: group(2).unwrap(),
+            message: group(3),
+            suppress_embed,
+        }));
+    }
+    Ok((input, Expression::Hyperlink { text: href, href, suppress_embed }))
+}
+
+// The text shown for a section of the AST. Emoji, mentions and everything else that isn't shown as
+// it's written become an object replacement character, which counts as shown content but can't
+// be part of a domain.
+fn shown_text(ast: &[Expression]) -> String {
+    ast.iter().map(|expression| match expression {
+        Expression::Text(text) | Expression::InlineCode(text) | Expression::MultilineCode { code: text, .. } => {
+            text.to_string()
+        }
+        Expression::Blockquote(a)
+        | Expression::Heading(_, a)
+        | Expression::Subtext(a)
+        | Expression::Spoiler(a)
+        | Expression::Underline(a)
+        | Expression::Strikethrough(a)
+        | Expression::Bold(a)
+        | Expression::Italics(a) => shown_text(a),
+        Expression::List { items, .. } => items.iter().map(|item| shown_text(item)).collect(),
+        Expression::Newline => String::from("\n"),
+        _ => String::from("\u{fffc}"),
+    }).collect()
+}
+
+// Checks if a hyperlink with alt text follows the rules discord has for them in messages, given the
+// alt text before and after it's parsed
+fn is_safe_masked_link(text: &str, content: &[Expression], href: &str) -> bool {
+    // Formatting and escapes aren't shown, so the URL check has to be on the parsed text
+    let shown = shown_text(content);
+    let shown = shown.trim();
+    !shown.is_empty()
+        // The alt text can't contain anything that looks like a different URL
+        && !shown.contains("://")
+        && !DOMAIN_RE.is_match(shown)
+        // Links can't be nested
+        && !text.contains('[')
+        && (href.starts_with("https://") || href.starts_with("http://"))
+}
+
+// Parses hyperlinks with alt text
+fn masked_link<'a>(input: &'a str, options: &ParseOptions) -> IResult<&'a str, (Expression<'a>, Option<Span>)> {
+    let start = input;
+    let (input, (text, (href, suppress_embed))) = pair(
+        delimited(tag("["), take_until("]"), tag("]")),
+        delimited(tag("("), |input| hyperlink_internals(input, options), tag(")")),
+    )(input)?;
+    // Links can't be nested, so the alt text is parsed without them
+    let text_options = ParseOptions {
+        link_schemes: Vec::new(),
+        masked_links: MaskedLinks::Disabled,
+        ..options.clone()
+    };
+    let (_, (content, span)) = parse_section(text, &text_options)?;
+    if options.masked_links == MaskedLinks::Restricted && !is_safe_masked_link(text, &content, href) {
+        return Err(nom::Err::Error(nom::error::make_error(start, nom::error::ErrorKind::Verify)));
+    }
+    Ok((input, (Expression::MaskedLink { content, href, suppress_embed }, Some(span))))
+}
+
+// Checks if the first line of a code block should be treated as the language of the code
+pub(crate) fn is_language_tag(line: &str) -> bool {
+    !line.is_empty() && line.chars().all(|c| c.is_ascii_alphanumeric() || "_+-.#".contains(c))
+}
+
+fn multiline_code<'a>(input: &'a str) -> IResult<&'a str, Expression<'a>> {
     let (input, multiline_code) = delimited(tag("```"), take_until("```"), tag("```"))(input)?;
-    Ok((input, Expression::MultilineCode(multiline_code)))
+    let (language, code) = match multiline_code.split_once('\n') {
+        // The first line is only a language tag if it's followed by some code
+        Some((language, code)) if is_language_tag(language) && !code.trim_matches('\n').is_empty() => {
+            (Some(language), code)
+        }
+        _ => (None, multiline_code),
+    };
+    Ok((input, Expression::MultilineCode { language, code: code.trim_matches('\n') }))
 }
 
-fn inline_code<'a>(input: &'a str) -> IResult<&str, Expression<'a>> {
+fn inline_code<'a>(input: &'a str) -> IResult<&'a str, Expression<'a>> {
     let (input, inline_code) = alt((
         // If the inline code block is delimited by ``
         delimited(tag("``"), take_until("``"), tag("``")),
@@ -125,28 +918,140 @@ fn inline_code<'a>(input: &'a str) -> IResult<&str, Expression<'a>> {
     Ok((input, Expression::InlineCode(inline_code)))
 }
 
-fn blockquote<'a>(input: &'a str) -> IResult<&str, Expression<'a>> {
-    let (input, blockquote) = map_parser(alt((
-        // Blockquote until end of line
-        delimited(tag("> "), is_not("\n"), tag("\n")),
-        // Special case for `> \n`
-        preceded(tag("> "), tag("\n")),
-        // Blockquote until end of file
-        preceded(tag("> "), is_not("\n")),
-    )), parse_section)(input)?;
-    Ok((input, Expression::Blockquote(blockquote)))
+fn blockquote<'a>(input: &'a str, options: &ParseOptions) -> IResult<&'a str, (Expression<'a>, Option<Span>)> {
+    let (input, (blockquote, span)) = alt((
+        // Multi-line blockquote until end of file
+        map_parser(
+            preceded(tag(">>> "), verify(rest, |s: &str| !s.is_empty())),
+            |input| parse_blockquote(input, options),
+        ),
+        map_parser(alt((
+            // Blockquote until end of line
+            delimited(tag("> "), is_not("\n"), tag("\n")),
+            // Special case for `> \n`
+            preceded(tag("> "), tag("\n")),
+            // Blockquote until end of file
+            preceded(tag("> "), is_not("\n")),
+        )), |input| parse_blockquote(input, options)),
+    ))(input)?;
+    Ok((input, (Expression::Blockquote(blockquote), Some(span))))
+}
+
+// Takes the rest of the line, consuming the succeeding newline if it exists
+fn rest_of_line(input: &str) -> IResult<&str, &str> {
+    alt((
+        // Until end of line
+        terminated(is_not("\n"), tag("\n")),
+        // Until end of file
+        is_not("\n"),
+    ))(input)
+}
+
+fn heading<'a>(input: &'a str, options: &ParseOptions) -> IResult<&'a str, (Expression<'a>, Option<Span>)> {
+    let (input, (level, (heading, span))) = pair(
+        // The heading level is the number of leading hashes, up to 3
+        terminated(alt((tag("###"), tag("##"), tag("#"))), tag(" ")),
+        map_parser(rest_of_line, |input| parse_section(input, options)),
+    )(input)?;
+    Ok((input, (Expression::Heading(level.len() as u8, heading), Some(span))))
+}
+
+fn subtext<'a>(input: &'a str, options: &ParseOptions) -> IResult<&'a str, (Expression<'a>, Option<Span>)> {
+    let (input, (subtext, span)) = map_parser(
+        preceded(tag("-# "), rest_of_line),
+        |input| parse_section(input, options),
+    )(input)?;
+    Ok((input, (Expression::Subtext(subtext), Some(span))))
+}
+
+// A single line of a list, before its content is parsed
+struct ListItem<'a> {
+    indent: usize,
+    // The number of the item if it's part of an ordered list
+    number: Option<u64>,
+    content: &'a str,
+}
+
+fn list_item(input: &str) -> IResult<&str, ListItem<'_>> {
+    let (input, (indent, number, content)) = tuple((
+        take_while(|c| c == ' '),
+        alt((
+            map(alt((tag("- "), tag("* "))), |_| None),
+            map_res(terminated(digit1, tag(". ")), |n: &str| n.parse().map(Some)),
+        )),
+        rest_of_line,
+    ))(input)?;
+    Ok((input, ListItem { indent: indent.len(), number, content }))
+}
+
+// Parses the content of a list item. The span of the item starts at the item's indentation, and its
+// content starts after the marker.
+fn list_item_content<'a>(
+    line: &'a str,
+    content: &'a str,
+    options: &ParseOptions,
+) -> IResult<&'a str, (Vec<Expression<'a>>, Span)> {
+    let (remaining, (content, mut span)) = parse_section(content, options)?;
+    span.range.start = line.as_ptr() as usize;
+    Ok((remaining, (content, span)))
+}
+
+// Parses the items of a list after its first item, including any nested lists. `start` is where
+// the first item begins.
+fn list_items<'a>(
+    start: &'a str,
+    mut input: &'a str,
+    first: ListItem<'a>,
+    options: &ParseOptions,
+) -> IResult<&'a str, (Expression<'a>, Option<Span>)> {
+    let ordered = first.number.is_some();
+    let (first_item, first_span) = list_item_content(start, first.content, options)?.1;
+    let (mut items, mut item_spans) = (vec![first_item], vec![first_span]);
+    loop {
+        match list_item(input) {
+            // Items indented further than this list belong to a list nested in the last item
+            Ok((remaining, item)) if item.indent > first.indent => {
+                let (remaining, (nested, nested_span)) = list_items(input, remaining, item, options)?;
+                items.last_mut().unwrap().push(nested);
+                // The nested list is part of the last item
+                let item_span = item_spans.last_mut().unwrap();
+                let nested_span = nested_span.unwrap();
+                item_span.range.end = nested_span.range.end;
+                item_span.children.push(nested_span);
+                input = remaining;
+            }
+            // Items with the same indentation continue this list, unless the kind of list changes
+            Ok((remaining, item)) if item.indent == first.indent && item.number.is_some() == ordered => {
+                let (item, item_span) = list_item_content(input, item.content, options)?.1;
+                items.push(item);
+                item_spans.push(item_span);
+                input = remaining;
+            }
+            _ => break,
+        }
+    }
+    let range = address_range(start, input);
+    Ok((input, (
+        Expression::List { ordered, start: first.number.unwrap_or(1), items },
+        Some(Span { range: range.clone(), content: range, children: item_spans }),
+    )))
+}
+
+fn list<'a>(input: &'a str, options: &ParseOptions) -> IResult<&'a str, (Expression<'a>, Option<Span>)> {
+    let (remaining, first) = list_item(input)?;
+    list_items(input, remaining, first, options)
 }
 
-fn spoiler<'a>(input: &'a str) -> IResult<&str, Expression<'a>> {
-    let (input, spoiler) = map_parser(
+fn spoiler<'a>(input: &'a str, options: &ParseOptions) -> IResult<&'a str, (Expression<'a>, Option<Span>)> {
+    let (input, (spoiler, span)) = map_parser(
         delimited(tag("||"), take_until("||"), tag("||")),
-        parse_section,
+        |input| parse_section(input, options),
     )(input)?;
-    Ok((input, Expression::Spoiler(spoiler)))
+    Ok((input, (Expression::Spoiler(spoiler), Some(span))))
 }
 
-fn underline<'a>(input: &'a str) -> IResult<&str, Expression<'a>> {
-    let (input, underline) = map_parser(
+fn underline<'a>(input: &'a str, options: &ParseOptions) -> IResult<&'a str, (Expression<'a>, Option<Span>)> {
+    let (input, (underline, span)) = map_parser(
         alt((
             // Special case with four surrounding underlines
             delimited(tag("____"), take_until("____"), tag("____")),
@@ -164,21 +1069,21 @@ fn underline<'a>(input: &'a str) -> IResult<&str, Expression<'a>> {
             ),
             delimited(tag("__"), take_until("__"), tag("__")),
         )),
-        parse_section,
+        |input| parse_section(input, options),
     )(input)?;
-    Ok((input, Expression::Underline(underline)))
+    Ok((input, (Expression::Underline(underline), Some(span))))
 }
 
-fn strikethrough<'a>(input: &'a str) -> IResult<&str, Expression<'a>> {
-    let (input, strikethrough) = map_parser(
+fn strikethrough<'a>(input: &'a str, options: &ParseOptions) -> IResult<&'a str, (Expression<'a>, Option<Span>)> {
+    let (input, (strikethrough, span)) = map_parser(
         delimited(tag("~~"), take_until("~~"), tag("~~")),
-        parse_section,
+        |input| parse_section(input, options),
     )(input)?;
-    Ok((input, Expression::Strikethrough(strikethrough)))
+    Ok((input, (Expression::Strikethrough(strikethrough), Some(span))))
 }
 
-fn bold<'a>(input: &'a str) -> IResult<&str, Expression<'a>> {
-    let (input, bold) = map_parser(
+fn bold<'a>(input: &'a str, options: &ParseOptions) -> IResult<&'a str, (Expression<'a>, Option<Span>)> {
+    let (input, (bold, span)) = map_parser(
         alt((
             // Special case with four surrounding asterisks
             delimited(tag("****"), take_until("****"), tag("****")),
@@ -196,61 +1101,138 @@ fn bold<'a>(input: &'a str) -> IResult<&str, Expression<'a>> {
             ),
             delimited(tag("**"), take_until("**"), tag("**")),
         )),
-        parse_section,
+        |input| parse_section(input, options),
     )(input)?;
-    Ok((input, Expression::Bold(bold)))
+    Ok((input, (Expression::Bold(bold), Some(span))))
 }
 
-fn italics<'a>(input: &'a str) -> IResult<&str, Expression<'a>> {
-    let (input, italics) = map_parser(
+fn italics<'a>(input: &'a str, options: &ParseOptions) -> IResult<&'a str, (Expression<'a>, Option<Span>)> {
+    let (input, (italics, span)) = map_parser(
         alt((
             delimited(tag("_"), is_not("_"), tag("_")),
             delimited(tag("*"), is_not("*"), tag("*")),
         )),
-        parse_section,
+        |input| parse_section(input, options),
     )(input)?;
-    Ok((input, Expression::Italics(italics)))
+    Ok((input, (Expression::Italics(italics), Some(span))))
 }
 
-fn apply_parsers(
+fn apply_parsers<'a>(
+    line_start: bool,
     allow_blockquote: bool,
-    md_hyperlinks: bool,
-    input: &str,
-) -> IResult<&str, Expression> {
+    options: &ParseOptions,
+    input: &'a str,
+) -> IResult<&'a str, (Expression<'a>, Option<Span>)> {
     alt((
-        map_opt(cond(allow_blockquote, blockquote), |o| o),
-        custom_emoji,
-        user,
-        role,
-        channel,
-        if md_hyperlinks {md_hyperlink} else {hyperlink},
-        multiline_code,
-        inline_code,
-        spoiler,
-        underline,
-        strikethrough,
-        bold,
-        italics,
+        // Expressions that are only allowed at the start of a line
+        alt((
+            map_opt(cond(line_start && allow_blockquote, |input| blockquote(input, options)), |o| o),
+            map_opt(cond(line_start, |input| heading(input, options)), |o| o),
+            map_opt(cond(line_start, |input| subtext(input, options)), |o| o),
+            map_opt(cond(line_start, |input| list(input, options)), |o| o),
+        )),
+        // Expressions without nested expressions
+        map(alt((
+            custom_emoji,
+            emoji,
+            map_opt(cond(options.emoji_shortcodes, emoji_shortcode), |o| o),
+            user,
+            role,
+            everyone,
+            here,
+            channel,
+            guild_navigation,
+            slash_command,
+            timestamp,
+            |input| hyperlink(input, options),
+            multiline_code,
+            inline_code,
+        )), |expression| (expression, None)),
+        map_opt(cond(options.masked_links != MaskedLinks::Disabled, |input| masked_link(input, options)), |o| o),
+        |input| spoiler(input, options),
+        |input| underline(input, options),
+        |input| strikethrough(input, options),
+        |input| bold(input, options),
+        |input| italics(input, options),
     ))(input)
 }
 
+// Returns the range in memory between the start of `start` and the start of `end`. These are made
+// relative to the start of the input string once parsing is done.
+fn address_range(start: &str, end: &str) -> Range<usize> {
+    start.as_ptr() as usize..end.as_ptr() as usize
+}
+
+// Returns the range in memory of a string slice
+fn slice_range(slice: &str) -> Range<usize> {
+    slice.as_ptr() as usize..slice.as_ptr() as usize + slice.len()
+}
+
+// The part of an expression without nested expressions that's used as the content of its span
+fn leaf_content<'a>(expression: &Expression<'a>) -> Option<&'a str> {
+    match *expression {
+        Expression::Text(content)
+        | Expression::CustomEmoji(content, _)
+        | Expression::Emoji(content)
+        | Expression::User(content)
+        | Expression::Role(content)
+        | Expression::Channel(content)
+        | Expression::InlineCode(content)
+        | Expression::MultilineCode { code: content, .. }
+        | Expression::Hyperlink { text: content, .. } => Some(content),
+        _ => None,
+    }
+}
+
+// The expressions parsed from a section of the input, along with their spans
+struct Section<'a> {
+    expressions: Vec<Expression<'a>>,
+    spans: Vec<Span>,
+}
+
+impl<'a> Section<'a> {
+    // Adds an expression covering `range`, taking the content and children of its span from
+    // `section` if it has nested expressions
+    fn push(&mut self, expression: Expression<'a>, range: Range<usize>, section: Option<Span>) {
+        let span = match section {
+            Some(section) => Span { range, content: section.content, children: section.children },
+            None => {
+                // Some expressions contain text that isn't from the input, like emoji from shortcodes
+                let content = leaf_content(&expression)
+                    .map(slice_range)
+                    .filter(|content| range.start <= content.start && content.end <= range.end)
+                    .unwrap_or_else(|| range.clone());
+                Span { range, content, children: Vec::new() }
+            }
+        };
+        self.expressions.push(expression);
+        self.spans.push(span);
+    }
+
+    fn push_text(&mut self, text: &'a str) {
+        self.push(Expression::Text(text), slice_range(text), None);
+    }
+}
+
 fn parse_internals<'a>(
     mut input: &'a str,
-    mut allow_blockquote: bool,
-    md_hyperlinks: bool,
-) -> IResult<&str, Vec<Expression<'a>>> {
+    mut line_start: bool,
+    allow_blockquote: bool,
+    options: &ParseOptions,
+) -> IResult<&'a str, Section<'a>> {
     // Attempt to parse everything until we encounter a newline/end of input
-    let mut result = Vec::new();
+    let mut result = Section { expressions: Vec::new(), spans: Vec::new() };
 
-    'outer: while input.len() != 0 {
+    'outer: while !input.is_empty() {
         for (i, c) in input.char_indices() {
             if c == '\n' {
-                // If it's a newline, we can parse blockquotes starting from the next character
+                // If it's a newline, we can parse blockquotes, headings, subtext and lists starting
+                // from the next character
                 if i > 0 {
-                    result.push(Expression::Text(&input[..i]))
+                    result.push_text(&input[..i])
                 }
-                result.push(Expression::Newline);
-                allow_blockquote = true;
+                result.push(Expression::Newline, slice_range(&input[i..i + 1]), None);
+                line_start = true;
                 // Remove the parsed part from `input` and restart the for loop
                 // We can safely do i + 1 because the input can't end with \n (it's stripped)
                 input = &input[i + 1..];
@@ -258,57 +1240,95 @@ fn parse_internals<'a>(
             } else if c == '¯' && input[i..].starts_with(r"¯\_(ツ)_/¯") {
                 // Parse shrug emote
                 if i > 0 {
-                    result.push(Expression::Text(&input[..i]))
+                    result.push_text(&input[..i])
                 }
                 // Push the shrug emote as Expression::Text
-                result.push(Expression::Text(r"¯\_(ツ)_/¯"));
+                let shrug = &input[i..i + r"¯\_(ツ)_/¯".len()];
+                result.push(Expression::Text(r"¯\_(ツ)_/¯"), slice_range(shrug), None);
                 // Remove the parsed part from `input` and restart the for loop
-                input = &input[i + r"¯\_(ツ)_/¯".len()..];
+                input = &input[i + shrug.len()..];
                 continue 'outer;
             } else if c == '\\' && input[i..].len() > 1 {
                 // If it's a backslash, we should escape the following character
                 if i > 0 {
-                    result.push(Expression::Text(&input[..i]))
+                    result.push_text(&input[..i])
                 }
-                // Push the escaped character as Expression::Text
-                let (char_pos, c) = input.char_indices().nth(i + 1).unwrap();
-                result.push(Expression::Text(&input[char_pos..char_pos + c.len_utf8()]));
+                // Push the escaped character as Expression::Text, with the backslash in its span
+                let c = input[i + 1..].chars().next().unwrap();
+                let escaped = &input[i + 1..i + 1 + c.len_utf8()];
+                result.push(Expression::Text(escaped), slice_range(&input[i..i + 1 + c.len_utf8()]), None);
+                // Escaped characters are text, so we're not at the start of a line anymore
+                line_start = false;
                 // Remove the parsed part from `input` and restart the for loop
-                input = &input[char_pos + c.len_utf8()..];
+                input = &input[i + 1 + c.len_utf8()..];
                 continue 'outer;
             }
-            if let Ok((remaining, expr)) = apply_parsers(allow_blockquote, md_hyperlinks, &input[i..]) {
-                // Don't reset blockquote if we just matched on a blockquote because it consumes a
-                // succeeding newline if it exists, and if it doesn't, `allow_blockquote` doesn't
-                // matter anyway
-                if !matches!(expr, Expression::Blockquote(_)) {
-                    // Reset allow_blockquote because we're not immediately after a newline
-                    allow_blockquote = false;
+            if let Ok((remaining, (expr, section))) = apply_parsers(line_start, allow_blockquote, options, &input[i..]) {
+                // Don't reset `line_start` if we just matched on a blockquote, heading, subtext or
+                // list because they consume a succeeding newline if it exists, and if it doesn't,
+                // `line_start` doesn't matter anyway
+                if !matches!(
+                    expr,
+                    Expression::Blockquote(_)
+                        | Expression::Heading(_, _)
+                        | Expression::Subtext(_)
+                        | Expression::List { .. }
+                ) {
+                    // Reset line_start because we're not immediately after a newline
+                    line_start = false;
                 }
                 // Add the text up to the parsed expression as Expression::Text
                 if i > 0 {
-                    result.push(Expression::Text(&input[..i]))
+                    result.push_text(&input[..i])
                 }
                 // Add the parsed expression
-                result.push(expr);
+                result.push(expr, address_range(&input[i..], remaining), section);
                 // Remove the parsed part from `input` and restart the for loop
                 input = remaining;
                 continue 'outer;
             } else {
-                allow_blockquote = false;
+                line_start = false;
             }
         }
-        if input.len() != 0 {
-            result.push(Expression::Text(input));
-            input = "";
+        if !input.is_empty() {
+            result.push_text(input);
+            input = &input[input.len()..];
         }
     }
 
     Ok((input, result))
 }
 
-fn parse_section<'a>(mut input: &'a str) -> IResult<&str, Vec<Expression<'a>>> {
-    parse_internals(&mut input, false, false)
+// Parses a section of the input, returning the span of the whole section with the spans of the
+// parsed expressions as its children
+fn parse_section<'a>(
+    input: &'a str,
+    options: &ParseOptions,
+) -> IResult<&'a str, (Vec<Expression<'a>>, Span)> {
+    let (remaining, section) = parse_internals(input, false, true, options)?;
+    let range = slice_range(input);
+    Ok((remaining, (section.expressions, Span { range: range.clone(), content: range, children: section.spans })))
+}
+
+// Blockquotes can't be nested, but the content of a blockquote, and every line of a multi-line
+// blockquote, can start with any of the other expressions that are only allowed at the start of a
+// line
+fn parse_blockquote<'a>(
+    input: &'a str,
+    options: &ParseOptions,
+) -> IResult<&'a str, (Vec<Expression<'a>>, Span)> {
+    let (remaining, section) = parse_internals(input, true, false, options)?;
+    let range = slice_range(input);
+    Ok((remaining, (section.expressions, Span { range: range.clone(), content: range, children: section.spans })))
+}
+
+// Converts the memory ranges in a span to byte offsets from `base`
+fn make_relative(span: &mut Span, base: usize) {
+    span.range = span.range.start - base..span.range.end - base;
+    span.content = span.content.start - base..span.content.end - base;
+    for child in &mut span.children {
+        make_relative(child, base);
+    }
 }
 
 /// Parses the given input string as Discord MarkDown and returns a vector of `Expression`s
@@ -324,11 +1344,15 @@ fn parse_section<'a>(mut input: &'a str) -> IResult<&str, Vec<Expression<'a>>> {
 ///     Blockquote(vec![Text("Can someone link the rust website?")]),
 ///     User("123456789123456789"),
 ///     Text(" "),
-///     Hyperlink("https://www.rust-lang.org", "https://www.rust-lang.org"),
+///     Hyperlink {
+///         text: "https://www.rust-lang.org",
+///         href: "https://www.rust-lang.org",
+///         suppress_embed: false,
+///     },
 /// ]);
 /// ```
-pub fn parse(mut input: &str) -> Vec<Expression> {
-    parse_internals(&mut input, true, false).unwrap().1
+pub fn parse(input: &str) -> Vec<Expression<'_>> {
+    parse_with_options(input, &ParseOptions::default())
 }
 
 /// Parses the given input string as Discord MarkDown with support for hyperlinks with alt text
@@ -337,13 +1361,152 @@ pub fn parse(mut input: &str) -> Vec<Expression> {
 /// ```
 /// use discord_markdown::parser::{parse_with_md_hyperlinks, Expression::*};
 ///
-/// let ast = parse_with_md_hyperlinks("_link_: [example](https://example.com)");
+/// let ast = parse_with_md_hyperlinks("_link_: [**example**](https://example.com)");
 /// assert_eq!(ast, vec![
 ///     Italics(vec![Text("link")]),
 ///     Text(": "),
-///     Hyperlink("example", "https://example.com"),
+///     MaskedLink {
+///         content: vec![Bold(vec![Text("example")])],
+///         href: "https://example.com",
+///         suppress_embed: false,
+///     },
 /// ]);
 /// ```
-pub fn parse_with_md_hyperlinks(mut input: &str) -> Vec<Expression> {
-    parse_internals(&mut input, true, true).unwrap().1
+pub fn parse_with_md_hyperlinks(input: &str) -> Vec<Expression<'_>> {
+    parse_with_options(input, &ParseOptions {
+        masked_links: MaskedLinks::Unrestricted,
+        ..ParseOptions::default()
+    })
+}
+
+/// Parses the given input string as Discord MarkDown using the given `ParseOptions` and returns a
+/// vector of `Expression`s
+///
+/// ```
+/// use discord_markdown::parser::{parse_with_options, ParseOptions, Expression::*};
+///
+/// let ast = parse_with_options(":thumbsup: :unknown:", &ParseOptions {
+///     emoji_shortcodes: true,
+///     ..ParseOptions::default()
+/// });
+/// assert_eq!(ast, vec![Emoji("👍"), Text(" :unknown:")]);
+/// ```
+pub fn parse_with_options<'a>(input: &'a str, options: &ParseOptions) -> Vec<Expression<'a>> {
+    parse_internals(input, true, true, options).unwrap().1.expressions
+}
+
+/// Parses the given input string as Discord MarkDown and returns a vector of `Expression`s along
+/// with a vector of their `Span`s in the input
+///
+/// The spans have the same structure as the AST: the span at each index belongs to the
+/// `Expression` at the same index, and the children of a span belong to the expressions nested in
+/// that `Expression`.
+///
+/// ```
+/// use discord_markdown::parser::{parse_spanned, Span, Expression::*};
+///
+/// let (ast, spans) = parse_spanned("hi **there**");
+/// assert_eq!(ast, vec![Text("hi "), Bold(vec![Text("there")])]);
+/// assert_eq!(spans, vec![
+///     Span { range: 0..3, content: 0..3, children: vec![] },
+///     Span {
+///         range: 3..12,
+///         content: 5..10,
+///         children: vec![Span { range: 5..10, content: 5..10, children: vec![] }],
+///     },
+/// ]);
+/// ```
+pub fn parse_spanned(input: &str) -> (Vec<Expression<'_>>, Vec<Span>) {
+    parse_spanned_with_options(input, &ParseOptions::default())
+}
+
+/// Parses the given input string as Discord MarkDown using the given `ParseOptions` and returns a
+/// vector of `Expression`s along with a vector of their `Span`s in the input. See `parse_spanned`
+/// for how the spans are structured.
+pub fn parse_spanned_with_options<'a>(
+    input: &'a str,
+    options: &ParseOptions,
+) -> (Vec<Expression<'a>>, Vec<Span>) {
+    let mut section = parse_internals(input, true, true, options).unwrap().1;
+    for span in &mut section.spans {
+        make_relative(span, input.as_ptr() as usize);
+    }
+    (section.expressions, section.spans)
+}
+
+// Splits the ID of a custom emoji in the AST into the ID and whether it's animated
+pub(crate) fn split_emoji_id(id: &str) -> (&str, bool) {
+    match id.strip_suffix(".gif") {
+        Some(id) => (id, true),
+        None => (id.trim_end_matches(".png"), false),
+    }
+}
+
+fn add_references<'a>(references: &mut References<'a>, ast: &'a [Expression<'a>]) {
+    fn push<T: PartialEq>(list: &mut Vec<T>, item: T) {
+        if !list.contains(&item) {
+            list.push(item);
+        }
+    }
+
+    for expression in ast {
+        match expression {
+            Expression::User(id) => push(&mut references.users, *id),
+            Expression::Role(id) => push(&mut references.roles, *id),
+            Expression::Everyone => references.everyone = true,
+            Expression::Here => references.here = true,
+            Expression::Channel(channel) => push(&mut references.channels, *channel),
+            Expression::MessageLink { guild, channel, message, .. } => {
+                push(&mut references.channels, *channel);
+                push(&mut references.message_links, MessageLinkReference { guild, channel, message: *message });
+            }
+            Expression::CustomEmoji(name, id) => {
+                let (id, animated) = split_emoji_id(id);
+                push(&mut references.emoji, CustomEmojiReference { name, id, animated });
+            }
+            Expression::Hyperlink { href, .. } => push(&mut references.urls, *href),
+            Expression::MaskedLink { content, href, .. } => {
+                add_references(references, content);
+                push(&mut references.urls, *href);
+            }
+            Expression::Blockquote(a)
+            | Expression::Heading(_, a)
+            | Expression::Subtext(a)
+            | Expression::Spoiler(a)
+            | Expression::Underline(a)
+            | Expression::Strikethrough(a)
+            | Expression::Bold(a)
+            | Expression::Italics(a) => add_references(references, a),
+            Expression::List { items, .. } => {
+                for item in items {
+                    add_references(references, item);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Finds the users, roles, channels, custom emoji, URLs and messages referenced in a vector of
+/// `Expression`s, and whether it mentions `@everyone` or `@here`
+///
+/// Mentions and links in code blocks and inline code aren't parsed, so they aren't included.
+///
+/// ```
+/// use discord_markdown::parser::{parse, references};
+///
+/// let ast = parse("<@123> **see <#456>**, <@123> `<@789>` https://example.com @here");
+/// let found = references(&ast);
+/// assert_eq!(found.users, vec!["123"]);
+/// assert_eq!(found.channels, vec!["456"]);
+/// assert_eq!(found.urls, vec!["https://example.com"]);
+/// assert!(found.here && !found.everyone);
+///
+/// let ast = parse("https://canary.discord.com/channels/1/2/3");
+/// assert_eq!(references(&ast).message_links[0].url(), "https://discord.com/channels/1/2/3");
+/// ```
+pub fn references<'a>(ast: &'a [Expression<'a>]) -> References<'a> {
+    let mut references = References::default();
+    add_references(&mut references, ast);
+    references
 }
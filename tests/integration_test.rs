@@ -1,4 +1,4 @@
-use discord_markdown::{convertor::*, parser::{parse, parse_with_md_hyperlinks}};
+use discord_markdown::{convertor::*, parser::{parse, parse_with_md_hyperlinks, parse_with_options, Expression::*, MaskedLinks, ParseOptions}};
 
 #[test]
 fn convertor_basic() {
@@ -14,6 +14,48 @@ fn convertor_nested() {
     ), "foo <em>&gt; foo</em> <em>bar<br><blockquote>foo</blockquote></em> <span class=\"spoiler\"><strong><em><u><span class=\"strikethrough\">foo<br>bar</span></u></em></strong></span>");
 }
 
+#[test]
+fn convertor_code_blocks() {
+    assert_eq!(to_html(
+        parse("```py\n  foo\nbar\n``````\nbaz```"),
+    ), "<pre class=\"multiline_code language-py\">  foo<br>bar</pre><pre class=\"multiline_code\">baz</pre>");
+    // Code is shown as it's written
+    assert_eq!(to_html(
+        parse("```js\n<script>alert(1)</script>``` `<b>`"),
+    ), "<pre class=\"multiline_code language-js\">&lt;script&gt;alert(1)&lt;/script&gt;</pre> <span class=\"inline_code\">&lt;b&gt;</span>");
+}
+
+#[test]
+fn convertor_multiline_blockquote() {
+    assert_eq!(to_html(
+        parse("> foo\n>>> _bar\nbaz_\nqux"),
+    ), "<blockquote>foo</blockquote><blockquote><em>bar<br>baz</em><br>qux</blockquote>");
+}
+
+#[test]
+fn convertor_headings() {
+    assert_eq!(to_html(
+        parse("# foo\n## _bar_\nbaz ### qux"),
+    ), "<h1>foo</h1><h2><em>bar</em></h2>baz ### qux");
+}
+
+#[test]
+fn convertor_subtext() {
+    assert_eq!(to_html(
+        parse("foo\n-# __bar__\nbaz"),
+    ), "foo<br><div class=\"subtext\"><u>bar</u></div>baz");
+}
+
+#[test]
+fn convertor_lists() {
+    assert_eq!(to_html(
+        parse("- foo\n - _bar_\n2. baz\n3. qux"),
+    ), "<ul><li>foo<ul><li><em>bar</em></li></ul></li></ul><ol start=\"2\"><li>baz</li><li>qux</li></ol>");
+    assert_eq!(to_html(
+        parse("> - foo\n> - bar"),
+    ), "<blockquote><ul><li>foo</li></ul></blockquote><blockquote><ul><li>bar</li></ul></blockquote>");
+}
+
 #[test]
 fn convertor_regex() {
     assert_eq!(to_html_with_callbacks(
@@ -25,12 +67,407 @@ fn convertor_regex() {
     ), "<span class=\"channel\" data-id=\"1234567890\">#1234567890</span><div class=\"role\" style=\"color: #ff00ff\">@1234567890<span style=\"background-color: #ff00ff\"></span></div><span class=\"user\">@1234567890</span><span class=\"user\">@1234567890</span><img src=\"1234567890.png\" alt=\"foo\" class=\"emoji\" title=\"foo\"></img><img src=\"1234567890.gif\" alt=\"foo\" class=\"emoji\" title=\"foo\"></img>");
 }
 
+#[test]
+fn convertor_emoji() {
+    assert_eq!(to_html(
+        parse("🎉 🎉"),
+    ), "<span class=\"emoji wumboji\">🎉</span> <span class=\"emoji wumboji\">🎉</span>");
+    assert_eq!(to_html_with_callbacks(
+        parse("🎉<:foo:1234567890>"),
+        |filename| (filename.to_string(), None),
+        |id| (id.to_string(), None),
+        |id| (id.to_string(), None),
+        |id| (id.to_string(), None),
+    ), "<span class=\"emoji wumboji\">🎉</span><img src=\"1234567890.png\" alt=\"foo\" class=\"emoji wumboji\" title=\"foo\"></img>");
+    assert_eq!(to_html(
+        parse("yay 🎉"),
+    ), "yay <span class=\"emoji\">🎉</span>");
+}
+
+#[test]
+fn convertor_everyone_here() {
+    assert_eq!(to_html(
+        parse(r"@everyone \@here **@here**"),
+    ), "<span class=\"user everyone\">@everyone</span> @here <strong><span class=\"user here\">@here</span></strong>");
+}
+
+#[test]
+fn convertor_guild_navigation() {
+    assert_eq!(to_html(
+        parse("<id:customize> <id:linked-roles:1234567890>"),
+    ), "<span class=\"channel guild_navigation\" data-kind=\"customize\">Channels &amp; Roles</span> <span class=\"channel guild_navigation\" data-kind=\"linked-roles\" data-id=\"1234567890\">Linked Roles</span>");
+}
+
+#[test]
+fn convertor_slash_commands() {
+    assert_eq!(to_html(
+        parse("</ping:1234567890> </settings user color:1234567890>"),
+    ), "<span class=\"slash_command\" data-id=\"1234567890\">/ping</span> <span class=\"slash_command\" data-id=\"1234567890\">/settings user color</span>");
+    assert_eq!(to_html_with_resolver(
+        parse("</settings user color:1234567890>"),
+        &TestResolver,
+    ), "<span class=\"slash_command\" data-id=\"1234567890\">/settings-user-color</span>");
+}
+
+#[test]
+fn convertor_timestamps() {
+    let datetime = "<time class=\"timestamp\" datetime=\"2021-04-20T21:20:30Z\"";
+    assert_eq!(to_html(
+        parse("<t:1618953630:t> <t:1618953630:T> <t:1618953630:d> <t:1618953630:D>"),
+    ), format!(
+        "{0} data-style=\"t\">21:20</time> {0} data-style=\"T\">21:20:30</time> \
+        {0} data-style=\"d\">20/04/2021</time> {0} data-style=\"D\">20 April 2021</time>",
+        datetime,
+    ));
+    assert_eq!(to_html(
+        parse("<t:1618953630> <t:951782400:F> <t:-86401:F>"),
+    ), format!(
+        "{} data-style=\"f\">20 April 2021 21:20</time> \
+        <time class=\"timestamp\" datetime=\"2000-02-29T00:00:00Z\" data-style=\"F\">Tuesday, 29 February 2000 00:00</time> \
+        <time class=\"timestamp\" datetime=\"1969-12-30T23:59:59Z\" data-style=\"F\">Tuesday, 30 December 1969 23:59</time>",
+        datetime,
+    ));
+}
+
+// Renders relative timestamps relative to a fixed time
+struct FixedTime;
+
+impl Renderer for FixedTime {
+    fn now(&self) -> i64 {
+        1618953630
+    }
+}
+
+impl MentionResolver for FixedTime {
+    fn now(&self) -> i64 {
+        1618953630
+    }
+}
+
+#[test]
+fn convertor_relative_timestamps() {
+    assert_eq!(
+        FixedTime.render(parse("<t:1618953600:R>")),
+        "<time class=\"timestamp\" datetime=\"2021-04-20T21:20:00Z\" data-style=\"R\">a few seconds ago</time>",
+    );
+    let relative: Vec<_> = [1618971630, 1613769630, 1524345630, 1650489630]
+        .iter()
+        .map(|unix| {
+            let html = FixedTime.render(parse(&format!("<t:{}:R>", unix)));
+            html[html.find('>').unwrap() + 1..html.len() - "</time>".len()].to_owned()
+        })
+        .collect();
+    assert_eq!(relative, vec!["in 5 hours", "2 months ago", "3 years ago", "in a year"]);
+    // Resolvers give the time for plain text and resolved HTML
+    assert_eq!(to_plain_text_with_resolver(parse("<t:1618971630:R>"), false, &FixedTime), "in 5 hours");
+    assert_eq!(
+        to_html_with_resolver(parse("<t:1618953600:R>"), &FixedTime),
+        "<time class=\"timestamp\" datetime=\"2021-04-20T21:20:00Z\" data-style=\"R\">a few seconds ago</time>",
+    );
+}
+
+#[test]
+fn convertor_message_links() {
+    let html = "<a class=\"message_link\" href=\"https://discord.com/channels/1234/5678/9012\" target=\"_blank\">\
+    <span class=\"channel\" data-id=\"5678\">#general</span>";
+    assert_eq!(to_html_with_callbacks(
+        parse("https://discord.com/channels/1234/5678/9012 <https://ptb.discord.com/channels/@me/5678>"),
+        |filename| (filename.to_string(), None),
+        |id| (id.to_string(), None),
+        |id| (id.to_string(), None),
+        |_| ("general".to_string(), None),
+    ), format!(
+        "{}</a> \
+        <a class=\"message_link\" href=\"https://discord.com/channels/@me/5678\" target=\"_blank\" data-suppress-embed=\"true\">\
+        <span class=\"channel\" data-id=\"5678\">#general</span></a>",
+        html,
+    ));
+    assert_eq!(
+        to_html(parse("https://discord.com/channels/1234/5678/9012")),
+        html.replace("#general", "#5678") + "</a>",
+    );
+}
+
 #[test]
 fn convertor_hyperlinks() {
     assert_eq!(to_html(
         parse("<https://www.example.com/> https://example.com [foo](https://example.com/) [foo](<http://example.com>)"),
-    ), "<a href=\"https://www.example.com/\" target=\"_blank\">https://www.example.com/</a> <a href=\"https://example.com\" target=\"_blank\">https://example.com</a> [foo](<a href=\"https://example.com/\" target=\"_blank\">https://example.com/</a>) [foo](<a href=\"http://example.com\" target=\"_blank\">http://example.com</a>)");
+    ), "<a href=\"https://www.example.com/\" target=\"_blank\" data-suppress-embed=\"true\">https://www.example.com/</a> <a href=\"https://example.com\" target=\"_blank\">https://example.com</a> [foo](<a href=\"https://example.com/\" target=\"_blank\">https://example.com/</a>) [foo](<a href=\"http://example.com\" target=\"_blank\" data-suppress-embed=\"true\">http://example.com</a>)");
     assert_eq!(to_html(
         parse_with_md_hyperlinks("<https://www.example.com/> https://example.com [foo](https://example.com/) [foo](<http://example.com>)"),
-    ), "<a href=\"https://www.example.com/\" target=\"_blank\">https://www.example.com/</a> <a href=\"https://example.com\" target=\"_blank\">https://example.com</a> <a href=\"https://example.com/\" target=\"_blank\">foo</a> <a href=\"http://example.com\" target=\"_blank\">foo</a>");
+    ), "<a href=\"https://www.example.com/\" target=\"_blank\" data-suppress-embed=\"true\">https://www.example.com/</a> <a href=\"https://example.com\" target=\"_blank\">https://example.com</a> <a href=\"https://example.com/\" target=\"_blank\">foo</a> <a href=\"http://example.com\" target=\"_blank\" data-suppress-embed=\"true\">foo</a>");
+    // The alt text of masked links is parsed as markdown and escaped
+    let options = ParseOptions { masked_links: MaskedLinks::Restricted, ..ParseOptions::default() };
+    assert_eq!(to_html(
+        parse_with_options("[<img src=x onerror=alert(1)>](https://a.com) [**x** `y`](https://a.com)", &options),
+    ), "<a href=\"https://a.com\" target=\"_blank\">&lt;img src=x onerror=alert(1)&gt;</a> \
+    <a href=\"https://a.com\" target=\"_blank\"><strong>x</strong> <span class=\"inline_code\">y</span></a>");
+    // URLs can't break out of the attribute, and the text is escaped
+    assert_eq!(to_html(
+        parse("https://a.com/\"onmouseover=\"alert(1)\"x https://a.com/?a=1&b=2"),
+    ), "<a href=\"https://a.com/&quot;onmouseover=&quot;alert(1)&quot;x\" target=\"_blank\">https://a.com/\"onmouseover=\"alert(1)\"x</a> \
+    <a href=\"https://a.com/?a=1&amp;b=2\" target=\"_blank\">https://a.com/?a=1&amp;b=2</a>");
+}
+
+struct TestRenderer;
+
+impl Renderer for TestRenderer {
+    fn user(&self, id: &str) -> String {
+        format!("<a href=\"/users/{}\">@user</a>", id)
+    }
+
+    fn bold(&self, content: String) -> String {
+        format!("<b>{}</b>", content)
+    }
+
+    fn list(&self, _: bool, _: u64, items: Vec<String>) -> String {
+        items.join(", ")
+    }
+}
+
+#[test]
+fn convertor_renderer() {
+    assert_eq!(
+        TestRenderer.render(parse("hey <@1234567890>, **look** _here_\n- foo\n- **bar**")),
+        "hey <a href=\"/users/1234567890\">@user</a>, <b>look</b> <em>here</em><br>foo, <b>bar</b>",
+    );
+    let input = "> _foo bar_ **baz** <@1234567890> <t:1618953630:d> ||🎉|| `qux`\n- a\n2. b";
+    assert_eq!(HtmlRenderer.render(parse(input)), to_html(parse(input)));
+}
+
+struct TestResolver;
+
+impl MentionResolver for TestResolver {
+    // Only IDs starting with 0 don't exist
+    fn user(&self, id: &str) -> Option<UserInfo> {
+        Some(UserInfo {
+            name: String::from("Jane"),
+            avatar: Some(format!("/avatars/{}.png", id)),
+            color: Some(String::from("#ff0000")),
+        }).filter(|_| !id.starts_with('0'))
+    }
+
+    fn role(&self, id: &str) -> Option<RoleInfo> {
+        Some(RoleInfo { name: String::from("mods"), color: None }).filter(|_| !id.starts_with('0'))
+    }
+
+    fn channel(&self, id: &str) -> Option<ChannelInfo> {
+        Some(ChannelInfo { name: String::from("help"), kind: ChannelKind::Forum }).filter(|_| !id.starts_with('0'))
+    }
+
+    fn emoji(&self, id: &str, animated: bool) -> String {
+        format!("https://cdn.discordapp.com/emojis/{}.{}", id, if animated { "gif" } else { "webp" })
+    }
+
+    fn slash_command(&self, command: &str) -> String {
+        command.replace(' ', "-")
+    }
+
+    fn message(&self, id: &str) -> Option<String> {
+        Some(format!("message {}", id))
+    }
+}
+
+#[test]
+fn convertor_resolver() {
+    assert_eq!(to_html_with_resolver(
+        parse("<@1234> <@&5678> <#9012> <a:foo:3456> </ping:7890> https://discord.com/channels/1/2/3"),
+        &TestResolver,
+    ), "<span class=\"user\" style=\"color: #ff0000\"><img src=\"/avatars/1234.png\" class=\"avatar\"></img>@Jane</span> \
+    <div class=\"role\" style=\"color: #afafaf\">@mods<span style=\"background-color: #afafaf\"></span></div> \
+    <span class=\"channel\" data-id=\"9012\" data-kind=\"forum\">#help</span> \
+    <img src=\"https://cdn.discordapp.com/emojis/3456.gif\" alt=\"foo\" class=\"emoji\" title=\"foo\"></img> \
+    <span class=\"slash_command\" data-id=\"7890\">/ping</span> \
+    <a class=\"message_link\" href=\"https://discord.com/channels/1/2/3\" target=\"_blank\">\
+    <span class=\"channel\" data-id=\"2\" data-kind=\"forum\">#help</span> › <span class=\"message_preview\">message 3</span></a>");
+    assert_eq!(to_plain_text_with_resolver(
+        parse("<@1234> <@&5678> <#9012> <:foo:3456> https://discord.com/channels/1/2/3"),
+        false,
+        &TestResolver,
+    ), "@Jane @mods #help :foo: #help › message 3");
+    assert_eq!(to_html_with_resolver(
+        parse("<@01234> <@&05678> <#09012> https://discord.com/channels/1/02/3"),
+        &TestResolver,
+    ), "<span class=\"user unresolved\">@unknown-user</span> <span class=\"role unresolved\">@deleted-role</span> \
+    <span class=\"channel unresolved\" data-id=\"09012\">#deleted-channel</span> \
+    <a class=\"message_link\" href=\"https://discord.com/channels/1/02/3\" target=\"_blank\">\
+    <span class=\"channel unresolved\" data-id=\"02\">#deleted-channel</span> › <span class=\"message_preview\">message 3</span></a>");
+    assert_eq!(to_plain_text_with_resolver(
+        parse("<@01234> <@&05678> <#09012>"),
+        false,
+        &TestResolver,
+    ), "@unknown-user @deleted-role #deleted-channel");
+    let input = "||<@1234> <#9012>|| <a:foo:3456> https://discord.com/channels/1/2/3";
+    assert_eq!(ResolvedRenderer(&TestResolver).render(parse(input)), to_html_with_resolver(parse(input), &TestResolver));
+}
+
+// Resolves every message to the same preview
+struct Preview(&'static str);
+
+impl MentionResolver for Preview {
+    fn message(&self, _: &str) -> Option<String> {
+        Some(self.0.to_owned())
+    }
+}
+
+#[test]
+fn convertor_message_previews() {
+    // Message previews are user-written, so they're escaped
+    assert_eq!(to_html_with_resolver(
+        parse("https://discord.com/channels/1/2/3"),
+        &Preview("<img src=x onerror=alert(1)>"),
+    ), "<a class=\"message_link\" href=\"https://discord.com/channels/1/2/3\" target=\"_blank\">\
+    <span class=\"channel\" data-id=\"2\">#2</span> › \
+    <span class=\"message_preview\">&lt;img src=x onerror=alert(1)&gt;</span></a>");
+}
+
+struct Hostile;
+
+impl MentionResolver for Hostile {
+    fn user(&self, _: &str) -> Option<UserInfo> {
+        Some(UserInfo {
+            name: String::from("<b>Jane</b>"),
+            avatar: Some(String::from("\" onerror=\"alert(1)")),
+            color: Some(String::from("red\" onclick=\"alert(1)")),
+        })
+    }
+
+    fn role(&self, _: &str) -> Option<RoleInfo> {
+        Some(RoleInfo { name: String::from("<i>mods</i>"), color: Some(String::from("\"><script>")) })
+    }
+
+    fn channel(&self, _: &str) -> Option<ChannelInfo> {
+        Some(ChannelInfo { name: String::from("<u>help</u>"), kind: ChannelKind::Text })
+    }
+
+    fn slash_command(&self, command: &str) -> String {
+        format!("<script>{}</script>", command)
+    }
+}
+
+#[test]
+fn convertor_resolved_names() {
+    // Names, colors and commands from the resolver can be user-written, so they're escaped
+    assert_eq!(to_html_with_resolver(parse("<@1> <@&2> <#3> </ping:4>"), &Hostile), "\
+    <span class=\"user\" style=\"color: red&quot; onclick=&quot;alert(1)\">\
+    <img src=\"&quot; onerror=&quot;alert(1)\" class=\"avatar\"></img>@&lt;b&gt;Jane&lt;/b&gt;</span> \
+    <div class=\"role\" style=\"color: &quot;&gt;&lt;script&gt;\">@&lt;i&gt;mods&lt;/i&gt;\
+    <span style=\"background-color: &quot;&gt;&lt;script&gt;\"></span></div> \
+    <span class=\"channel\" data-id=\"3\">#&lt;u&gt;help&lt;/u&gt;</span> \
+    <span class=\"slash_command\" data-id=\"4\">/&lt;script&gt;ping&lt;/script&gt;</span>");
+}
+
+#[test]
+fn convertor_mention_ids() {
+    assert_eq!(MentionIds::collect(&parse(
+        "<@1> **<@2> <@1>** <@&3>\n> <#4> <a:foo:5> <:bar:6>\n- <:baz:5> https://discord.com/channels/7/8/9 `<@10>`",
+    )), MentionIds {
+        users: vec![String::from("1"), String::from("2")],
+        roles: vec![String::from("3")],
+        channels: vec![String::from("4"), String::from("8")],
+        emoji: vec![(String::from("5"), true), (String::from("6"), false)],
+    });
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn convertor_async() {
+    let html = futures::executor::block_on(to_html_async(
+        parse("<@1> <@2> <@&3> <#4> <:foo:5>"),
+        |ids| async move {
+            let mut resolved = ResolvedMentions::default();
+            resolved.users.insert(ids.users[0].clone(), UserInfo { name: String::from("Jane"), ..UserInfo::default() });
+            resolved.roles.insert(ids.roles[0].clone(), RoleInfo { name: String::from("mods"), color: None });
+            resolved.channels.insert(ids.channels[0].clone(), ChannelInfo { name: String::from("general"), ..ChannelInfo::default() });
+            resolved.emoji.insert(ids.emoji[0].0.clone(), String::from("/emoji/5.png"));
+            resolved
+        },
+    ));
+    assert_eq!(html, "<span class=\"user\">@Jane</span> <span class=\"user unresolved\">@unknown-user</span> \
+    <div class=\"role\" style=\"color: #afafaf\">@mods<span style=\"background-color: #afafaf\"></span></div> \
+    <span class=\"channel\" data-id=\"4\">#general</span> \
+    <img src=\"/emoji/5.png\" alt=\"foo\" class=\"emoji\" title=\"foo\"></img>");
+}
+
+#[test]
+fn convertor_markdown() {
+    // Markdown generated from the AST parses back into the same AST, for messages made up of
+    // pieces that run into each other in as many ways as possible
+    const PIECES: &[&str] = &[
+        "a", "b", "1", " ", "  ", "\n", "*", "_", "**", "__", "***", "___", "||", "~~", "~", "|", "`", "``", "```",
+        "\\", ">", "#", "-", ".", ":", "<", "[", "]", "(", ")", "> ", ">>> ", "# ", "-# ", "- ", "1. ", "2. ",
+        "<@1>", "🎉", "¯\\_(ツ)_/¯", "https://a.com", "<https://a.com>", "](https://a.com)",
+    ];
+    let masked_links = ParseOptions { masked_links: MaskedLinks::Unrestricted, ..ParseOptions::default() };
+    // A fixed xorshift generator, so failures can be reproduced
+    let mut state: u64 = 0x2545f4914f6cdd1d;
+    let mut random = |n: usize| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        (state % n as u64) as usize
+    };
+    // Starting with some that used to come out wrong
+    let mut inputs: Vec<String> = ["```a`\n```", "*\\b> c", "a__b_ c_", "- a\n  - b\n - c"].map(String::from).into();
+    for _ in 0..5_000 {
+        let len = random(10) + 1;
+        inputs.push((0..len).map(|_| PIECES[random(PIECES.len())]).collect());
+    }
+    for input in inputs {
+        for options in [&ParseOptions::default(), &masked_links] {
+            let ast = parse_with_options(&input, options);
+            let markdown = to_markdown(parse_with_options(&input, options));
+            assert_eq!(parse_with_options(&markdown, options), ast, "{:?} -> {:?}", input, markdown);
+        }
+    }
+
+    assert_eq!(to_markdown(vec![
+        Bold(vec![Italics(vec![Text("a")])]),
+        Text(" "),
+        Italics(vec![Bold(vec![Text("b")])]),
+        Text(" "),
+        Underline(vec![Italics(vec![Text("c")]), Text("d")]),
+        Text(" "),
+        Bold(vec![Text("e"), Italics(vec![Text("f")])]),
+        Text("*"),
+    ]), "***a*** _**b**_ __*c*d__ **e*f***\\*");
+    assert_eq!(to_markdown(vec![
+        Text("a"), Text("*"), Newline, Text("#"), Text(" b"), Newline, Text("*"),
+    ]), "a\\*\n\\# b\n\\*");
+    // Redacting a mention
+    let ast: Vec<_> = parse("hey <@123>, **look**").into_iter().map(|expression| match expression {
+        User(_) => Text("@redacted"),
+        expression => expression,
+    }).collect();
+    assert_eq!(to_markdown(ast), "hey @redacted, **look**");
+}
+
+#[test]
+fn convertor_plain_text() {
+    assert_eq!(to_plain_text(
+        parse("> **foo** _bar_\n# ~~baz~~ __qux__\n-# ||spoiler|| `*code*`\n```rs\nlet x = 1;\n```"),
+        false,
+    ), "foo bar\nbaz qux\nspoiler *code*\nlet x = 1;");
+    assert_eq!(to_plain_text(
+        parse("- foo\n  - _bar_\n2. baz\n3. qux\nthe end"),
+        false,
+    ), "- foo\n  - bar\n2. baz\n3. qux\nthe end");
+    assert_eq!(to_plain_text(parse("> \nfoo"), false), "\nfoo");
+    assert_eq!(to_plain_text(
+        parse_with_md_hyperlinks("||foo|| ||**bar**|| [docs](https://docs.rs) <id:customize> 🎉 <:foo:1234567890> <t:1618953630:d>"),
+        true,
+    ), "[spoiler] [spoiler] docs Channels & Roles 🎉 :foo: 20/04/2021");
+    assert_eq!(to_plain_text_with_resolver(
+        parse("<@1234567890> <@&1234567890> <#1234567890> @everyone </settings user:1234567890> https://discord.com/channels/1/2/3"),
+        false,
+        &TestResolver,
+    ), "@Jane @mods #help @everyone /settings-user #help › message 3");
+    assert_eq!(to_plain_text_with_callbacks(
+        parse("<@1234567890> <@&1234567890> <#1234567890> @everyone <:foo:1234567890>"),
+        false,
+        |filename| (filename.to_string(), None),
+        |_| ("Jane".to_string(), None),
+        |_| ("mods".to_string(), Some(String::from("#ff00ff"))),
+        |_| ("general".to_string(), None),
+    ), "@Jane @mods #general @everyone :foo:");
 }